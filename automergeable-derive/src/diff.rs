@@ -0,0 +1,154 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{
+    punctuated::Punctuated, token::Comma, Data, DataEnum, DataStruct, DeriveInput, Field, Fields,
+    Variant,
+};
+
+use crate::{to, utils};
+
+pub fn diff(input: &DeriveInput) -> TokenStream {
+    let crate_path = utils::crate_path(input);
+    let t_name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let body = match &input.data {
+        Data::Struct(DataStruct { fields, .. }) => diff_struct(fields, &crate_path),
+        Data::Enum(DataEnum { variants, .. }) => diff_enum(variants, &crate_path),
+        Data::Union(_) => panic!("this derive macro only works on structs with named fields"),
+    };
+    quote! {
+        #[automatically_derived]
+        impl #impl_generics #crate_path::Diff for #t_name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn changed_values(&self, old: &Self, path: automerge::Path, changed: &mut #crate_path::ChangedValues) {
+                #body
+            }
+        }
+    }
+}
+
+fn diff_struct(fields: &Fields, crate_path: &TokenStream) -> TokenStream {
+    fields_changed_values(
+        fields,
+        |i, f| {
+            let member = f.ident.as_ref().map_or_else(
+                || {
+                    let i = syn::Index::from(i);
+                    quote! {#i}
+                },
+                |ident| quote! {#ident},
+            );
+            (quote! {self.#member}, quote! {old.#member})
+        },
+        crate_path,
+    )
+}
+
+fn diff_enum(variants: &Punctuated<Variant, Comma>, crate_path: &TokenStream) -> TokenStream {
+    let variant_arms = variants.iter().map(|v| {
+        let v_name = &v.ident;
+        let v_name_string = v_name.to_string();
+        let binding = |prefix: &str, i: usize, f: &Field| {
+            f.ident.as_ref().map_or_else(
+                || format_ident!("{}_{}", prefix, i),
+                |ident| format_ident!("{}_{}", prefix, ident),
+            )
+        };
+        let pattern = |prefix: &str| match &v.fields {
+            Fields::Named(n) => {
+                let names = n.named.iter().enumerate().map(|(i, f)| {
+                    let name = &f.ident;
+                    let binding = binding(prefix, i, f);
+                    quote! { #name: #binding, }
+                });
+                quote! {{ #(#names)* }}
+            }
+            Fields::Unnamed(u) => {
+                let items = u.unnamed.iter().enumerate().map(|(i, f)| {
+                    let binding = binding(prefix, i, f);
+                    quote! { #binding, }
+                });
+                quote! {( #(#items)* )}
+            }
+            Fields::Unit => quote! {},
+        };
+        let (new_pattern, old_pattern) = (pattern("new"), pattern("old"));
+        if v.fields.is_empty() {
+            quote! {
+                (Self::#v_name#new_pattern, Self::#v_name#old_pattern) => {}
+            }
+        } else {
+            let fields_changed_values = fields_changed_values(
+                &v.fields,
+                |i, f| {
+                    let (new, old) = (binding("new", i, f), binding("old", i, f));
+                    (quote! {(*#new)}, quote! {(*#old)})
+                },
+                crate_path,
+            );
+            quote! {
+                (Self::#v_name#new_pattern, Self::#v_name#old_pattern) => {
+                    let path = path.key(#v_name_string);
+                    #fields_changed_values
+                }
+            }
+        }
+    });
+    quote! {
+        #[allow(unreachable_patterns)]
+        match (self, old) {
+            #(#variant_arms)*
+            // a different variant so replace the whole value
+            _ => changed.push((path, Some(#crate_path::ToAutomerge::to_automerge(self)))),
+        }
+    }
+}
+
+/// Push the changed values of the fields, where `access` gives the expressions for the new and
+/// old values of a field.
+fn fields_changed_values<A>(fields: &Fields, access: A, crate_path: &TokenStream) -> TokenStream
+where
+    A: Fn(usize, &Field) -> (TokenStream, TokenStream),
+{
+    let field_changed_values = |i: usize, f: &Field, path: TokenStream| {
+        let (new, old) = access(i, f);
+        // fields with a representation are always leaves
+        if utils::field_attribute(&f.attrs, "representation").is_some() {
+            let repr = to::get_representation_type(&f.attrs, &new, crate_path);
+            quote! {
+                if #new != #old {
+                    changed.push((#path, Some(#repr)));
+                }
+            }
+        } else {
+            quote! {
+                #crate_path::Diff::changed_values(&#new, &#old, #path, changed);
+            }
+        }
+    };
+    match fields {
+        Fields::Named(n) => {
+            let fields = n.named.iter().enumerate().map(|(i, f)| {
+                let field_name_string = format_ident!("{}", f.ident.as_ref().unwrap()).to_string();
+                field_changed_values(i, f, quote! {path.clone().key(#field_name_string)})
+            });
+            quote! {
+                #(#fields)*
+            }
+        }
+        Fields::Unnamed(u) => {
+            if u.unnamed.len() == 1 {
+                field_changed_values(0, u.unnamed.first().unwrap(), quote! {path})
+            } else {
+                let fields = u.unnamed.iter().enumerate().map(|(i, f)| {
+                    let index = i as u32;
+                    field_changed_values(i, f, quote! {path.clone().index(#index)})
+                });
+                quote! {
+                    #(#fields)*
+                }
+            }
+        }
+        Fields::Unit => quote! {},
+    }
+}
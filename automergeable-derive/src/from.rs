@@ -2,7 +2,7 @@ use proc_macro2::TokenStream;
 use quote::{format_ident, quote};
 use syn::{
     punctuated::Punctuated, token::Comma, Attribute, Data, DataEnum, DataStruct, DeriveInput,
-    Fields, Ident, Lit, Meta, NestedMeta, Type, Variant,
+    Fields, Ident, Type, Variant,
 };
 
 use crate::utils;
@@ -20,12 +20,46 @@ fn from_automerge_struct(input: &DeriveInput, fields: &Fields) -> TokenStream {
     let t_name = &input.ident;
     let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
     let fields_from_automerge = fields_from_automerge(fields, None, &crate_path);
+    let version = match utils::version(input) {
+        Ok(version) => version,
+        Err(e) => return e.to_compile_error(),
+    };
+    let migrations = version.map(|version| {
+        // each version migrates from the one before it, with migrate_vN_to_vN+1 functions
+        let steps = (1..version).map(|from| {
+            let step = format_ident!("migrate_v{}_to_v{}", from, from + 1);
+            quote! { #from => Self::#step(value), }
+        });
+        quote! {
+            fn version() -> ::std::option::Option<u64> {
+                ::std::option::Option::Some(#version)
+            }
+
+            fn migrate(version: u64, value: automerge::Value) -> automerge::Value {
+                #[allow(clippy::match_single_binding)]
+                match version {
+                    #(#steps)*
+                    _ => value,
+                }
+            }
+        }
+    });
+    // versioned values are migrated here so that nested ones are too, not just document roots
+    let migrate = migrations.as_ref().map(|_| {
+        quote! {
+            let migrated = #crate_path::migrate::<Self>(value)?;
+            let value = migrated.as_ref().unwrap_or(value);
+        }
+    });
     quote! {
         #[automatically_derived]
         impl #impl_generics #crate_path::FromAutomerge for #t_name #ty_generics #where_clause {
             fn from_automerge(value: &automerge::Value) -> ::std::result::Result<Self, #crate_path::FromAutomergeError> {
+                #migrate
                 #fields_from_automerge
             }
+
+            #migrations
         }
     }
 }
@@ -95,31 +129,7 @@ fn get_representation_type(
     value_for_field: &TokenStream,
     crate_path: &TokenStream,
 ) -> TokenStream {
-    let mut ty = None;
-    for a in attrs {
-        match a.parse_meta().unwrap() {
-            Meta::NameValue(_) | Meta::Path(_) => {}
-            Meta::List(meta) => {
-                if Some("automergeable".to_owned())
-                    == meta.path.get_ident().map(ToString::to_string)
-                {
-                    for m in meta.nested {
-                        match m {
-                            NestedMeta::Meta(meta) => match meta {
-                                Meta::Path(_) | Meta::List(_) => {}
-                                Meta::NameValue(n) => {
-                                    if let Lit::Str(lit) = &n.lit {
-                                        ty = Some(lit.value())
-                                    }
-                                }
-                            },
-                            NestedMeta::Lit(_) => {}
-                        }
-                    }
-                }
-            }
-        }
-    }
+    let ty = utils::field_attribute(attrs, "representation");
     match ty.map(|s| s.to_lowercase()).as_deref() {
         Some("text") => {
             quote! {
@@ -162,6 +172,35 @@ fn get_representation_type(
                 }
             }
         }
+        Some("cursor") => {
+            quote! {
+                if let Some(value) = #value_for_field {
+                    match value {
+                        automerge::Value::Primitive(automerge::Primitive::Cursor(cursor)) => {
+                            ::std::option::Option::Some(cursor.clone())
+                        }
+                        automerge::Value::Primitive(automerge::Primitive::Null) => ::std::option::Option::None,
+                        _ => {
+                            return Err(#crate_path::FromAutomergeError::WrongType {
+                                found: value.clone(),
+                                expected: "a primitive cursor".to_owned(),
+                            })
+                        }
+                    }
+                } else {
+                    <#field_ty>::default()
+                }
+            }
+        }
+        Some("table") => {
+            quote! {
+                if let Some(value) = #value_for_field {
+                    <#field_ty>::from_automerge(&#crate_path::table_to_map(value))?
+                } else {
+                    <#field_ty>::default()
+                }
+            }
+        }
         _ => {
             quote! {
                 if let Some(value) = #value_for_field {
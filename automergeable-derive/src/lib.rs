@@ -5,6 +5,7 @@ use proc_macro::TokenStream;
 use quote::quote;
 use syn::{parse_macro_input, DeriveInput};
 
+mod diff;
 mod from;
 mod to;
 mod utils;
@@ -37,6 +38,14 @@ pub fn automergeable(input: TokenStream) -> TokenStream {
 /// `#[automergeable(representation = "counter")]`: Converts an [`i64`] into an [`automerge::Primitive::Counter`] type.
 ///
 /// `#[automergeable(representation = "timestamp")]`: Converts an [`i64`] into an [`automerge::Primitive::Timestamp`] type.
+///
+/// `#[automergeable(representation = "table")]`: Converts a map, such as a [`HashMap`](std::collections::HashMap), into an [`automerge::Value::Table`] type.
+///
+/// `#[automergeable(representation = "cursor")]`: Converts an [`Option`] of an automerge cursor into an [`automerge::Primitive::Cursor`] type, or a null if there is no cursor. Fields of an [`Option`] of [`Cursor`](automergeable_traits::Cursor) don't need this.
+///
+/// `#[automergeable(version = 2)]` on a struct with named fields: Stores the schema version in its map under [`VERSION_KEY`](automergeable_traits::VERSION_KEY).
+///
+/// `#[automergeable(key = "id")]`: Identifies the elements of a list field by their `id` field when diffing, so that reordered, inserted and removed elements are matched up by key rather than by position.
 #[proc_macro_derive(ToAutomerge, attributes(automergeable))]
 pub fn to_automerge(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
@@ -52,8 +61,25 @@ pub fn to_automerge(input: TokenStream) -> TokenStream {
 /// `#[automergeable(representation = "counter")]`: Converts an [`automerge::Primitive::Counter`] type to an [`i64`].
 ///
 /// `#[automergeable(representation = "timestamp")]`: Converts an [`automerge::Primitive::Timestamp`] type to an [`i64`].
+///
+/// `#[automergeable(representation = "table")]`: Converts an [`automerge::Value::Table`] type to a map, such as a [`HashMap`](std::collections::HashMap).
+///
+/// `#[automergeable(representation = "cursor")]`: Converts an [`automerge::Primitive::Cursor`] type, or a null, to an [`Option`] of an automerge cursor. Fields of an [`Option`] of [`Cursor`](automergeable_traits::Cursor) don't need this.
+///
+/// `#[automergeable(version = 2)]` on a struct with named fields: Sets the schema version so that values stored with older versions are migrated when converted, including when nested in other values. Each older version `N` needs a `fn migrate_vN_to_vN+1(automerge::Value) -> automerge::Value` on the type, such as `migrate_v1_to_v2`. Values without a version are version 1, so the version must be an integer of at least 1.
 #[proc_macro_derive(FromAutomerge, attributes(automergeable))]
 pub fn from_automerge(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
     from::from_automerge(&input).into()
 }
+
+/// Derive the [`Diff`](automergeable_traits::Diff) trait.
+///
+/// Each field is compared separately so only the fields which changed are converted. Fields with a
+/// `representation` attribute are compared as a whole and all other fields must implement `Diff`
+/// themselves. Enums are compared field by field when the variant is unchanged.
+#[proc_macro_derive(Diff, attributes(automergeable))]
+pub fn diff(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    diff::diff(&input).into()
+}
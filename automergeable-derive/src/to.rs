@@ -2,7 +2,7 @@ use proc_macro2::{Ident, Span, TokenStream};
 use quote::{format_ident, quote};
 use syn::{
     punctuated::Punctuated, token::Comma, Attribute, Data, DataEnum, DataStruct, DeriveInput,
-    Fields, Lit, Meta, NestedMeta, Variant,
+    Field, Fields, Variant,
 };
 
 use crate::utils;
@@ -19,22 +19,54 @@ fn to_automerge_struct(input: &DeriveInput, fields: &Fields) -> TokenStream {
     let crate_path = utils::crate_path(input);
     let t_name = &input.ident;
     let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
-    let fields_to_automerge = fields_to_automerge(fields, true, &crate_path);
+    let mut fields_to_automerge = fields_to_automerge(fields, true, &crate_path);
+    let version = match utils::version(input) {
+        Ok(version) => version,
+        Err(e) => return e.to_compile_error(),
+    };
+    if let Some(version) = version {
+        if !matches!(fields, Fields::Named(_)) {
+            panic!("the version attribute only works on structs with named fields")
+        }
+        fields_to_automerge = quote! {
+            let mut value = { #fields_to_automerge };
+            if let automerge::Value::Map(fields) = &mut value {
+                fields.insert(
+                    #crate_path::VERSION_KEY.into(),
+                    automerge::Value::Primitive(automerge::Primitive::Uint(#version)),
+                );
+            }
+            value
+        };
+    }
+    let fields_list_keys = fields_list_keys(fields, &format_ident!("keys"), &crate_path);
     quote! {
         #[automatically_derived]
         impl #impl_generics #crate_path::ToAutomerge for #t_name #ty_generics #where_clause {
             fn to_automerge(&self) -> automerge::Value {
                 #fields_to_automerge
             }
+
+            fn list_keys() -> #crate_path::ListKeys {
+                #[allow(unused_mut)]
+                let mut keys = #crate_path::ListKeys::new();
+                #fields_list_keys
+                keys
+            }
         }
     }
 }
 
 fn to_automerge_enum(input: &DeriveInput, variants: &Punctuated<Variant, Comma>) -> TokenStream {
+    match utils::version(input) {
+        Ok(None) => {}
+        Ok(Some(_)) => panic!("the version attribute only works on structs with named fields"),
+        Err(e) => return e.to_compile_error(),
+    }
     let crate_path = utils::crate_path(input);
     let t_name = &input.ident;
     let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
-    let variants = variants.iter().map(|v| {
+    let variant_arms = variants.iter().map(|v| {
         let v_name = &v.ident;
         let fields = match &v.fields {
             Fields::Named(n) => {
@@ -74,48 +106,42 @@ fn to_automerge_enum(input: &DeriveInput, variants: &Punctuated<Variant, Comma>)
             }
         }
     });
+    let variants_list_keys = variants.iter().filter(|v| !v.fields.is_empty()).map(|v| {
+        let v_name_string = v.ident.to_string();
+        let variant_keys = format_ident!("variant_keys");
+        let fields_list_keys = fields_list_keys(&v.fields, &variant_keys, &crate_path);
+        quote! {
+            #[allow(unused_mut)]
+            let mut #variant_keys = #crate_path::ListKeys::new();
+            #fields_list_keys
+            keys.extend_nested(#crate_path::KeyPathElement::Key(#v_name_string.into()), #variant_keys);
+        }
+    });
     quote! {
         #[automatically_derived]
         impl #impl_generics #crate_path::ToAutomerge for #t_name #ty_generics #where_clause {
             fn to_automerge(&self) -> automerge::Value {
                 match self {
-                    #(#variants)*
+                    #(#variant_arms)*
                 }
             }
+
+            fn list_keys() -> #crate_path::ListKeys {
+                #[allow(unused_mut)]
+                let mut keys = #crate_path::ListKeys::new();
+                #(#variants_list_keys)*
+                keys
+            }
         }
     }
 }
 
-fn get_representation_type(
+pub fn get_representation_type(
     attrs: &[Attribute],
     field_name: &TokenStream,
     crate_path: &TokenStream,
 ) -> TokenStream {
-    let mut ty = None;
-    for a in attrs {
-        match a.parse_meta().unwrap() {
-            Meta::NameValue(_) | Meta::Path(_) => {}
-            Meta::List(meta) => {
-                if Some("automergeable".to_owned())
-                    == meta.path.get_ident().map(ToString::to_string)
-                {
-                    for m in meta.nested {
-                        match m {
-                            NestedMeta::Meta(meta) => match meta {
-                                Meta::Path(_) | Meta::List(_) => {}
-                                Meta::NameValue(n) => {
-                                    if let Lit::Str(lit) = &n.lit {
-                                        ty = Some(lit.value())
-                                    }
-                                }
-                            },
-                            NestedMeta::Lit(_) => {}
-                        }
-                    }
-                }
-            }
-        }
-    }
+    let ty = utils::field_attribute(attrs, "representation");
     match ty.map(|s| s.to_lowercase()).as_deref() {
         Some("text") => {
             quote! {{
@@ -129,6 +155,17 @@ fn get_representation_type(
         Some("timestamp") => {
             quote! { automerge::Value::Primitive(automerge::Primitive::Timestamp(#field_name)) }
         }
+        Some("table") => {
+            quote! { #crate_path::map_to_table(#field_name.to_automerge()) }
+        }
+        Some("cursor") => {
+            quote! {
+                match &#field_name {
+                    ::std::option::Option::Some(cursor) => automerge::Value::Primitive(automerge::Primitive::Cursor(cursor.clone())),
+                    ::std::option::Option::None => automerge::Value::Primitive(automerge::Primitive::Null),
+                }
+            }
+        }
         _ => quote! { #field_name.to_automerge() },
     }
 }
@@ -198,3 +235,61 @@ fn fields_to_automerge(fields: &Fields, is_struct: bool, crate_path: &TokenStrea
         }
     }
 }
+
+/// Collect the keys of lists in the fields into the `keys` variable.
+fn fields_list_keys(fields: &Fields, keys: &Ident, crate_path: &TokenStream) -> TokenStream {
+    let field_list_keys = |f: &Field, prefix: Option<TokenStream>| {
+        let field_ty = &f.ty;
+        let key = utils::field_attribute(&f.attrs, "key").map(|key| {
+            let path = prefix.iter();
+            quote! {
+                #keys.insert(vec![#(#path)*], #key);
+            }
+        });
+        // fields with a representation other than a table are always leaves
+        let representation = utils::field_attribute(&f.attrs, "representation");
+        let nested = if representation.map_or(false, |r| !r.eq_ignore_ascii_case("table")) {
+            None
+        } else if let Some(prefix) = &prefix {
+            Some(quote! {
+                #keys.extend_nested(#prefix, <#field_ty as #crate_path::ToAutomerge>::list_keys());
+            })
+        } else {
+            Some(quote! {
+                #keys.merge(<#field_ty as #crate_path::ToAutomerge>::list_keys());
+            })
+        };
+        quote! {
+            #key
+            #nested
+        }
+    };
+    match fields {
+        Fields::Named(n) => {
+            let fields = n.named.iter().map(|f| {
+                let field_name_string = format_ident!("{}", f.ident.as_ref().unwrap()).to_string();
+                field_list_keys(
+                    f,
+                    Some(quote! {#crate_path::KeyPathElement::Key(#field_name_string.into())}),
+                )
+            });
+            quote! {
+                #(#fields)*
+            }
+        }
+        Fields::Unnamed(u) => {
+            if u.unnamed.len() == 1 {
+                field_list_keys(u.unnamed.first().unwrap(), None)
+            } else {
+                let fields = u.unnamed.iter().enumerate().map(|(i, f)| {
+                    let i = i as u32;
+                    field_list_keys(f, Some(quote! {#crate_path::KeyPathElement::Index(#i)}))
+                });
+                quote! {
+                    #(#fields)*
+                }
+            }
+        }
+        Fields::Unit => quote! {},
+    }
+}
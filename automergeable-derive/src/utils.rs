@@ -1,6 +1,6 @@
 use proc_macro2::{Ident, TokenStream};
 use quote::quote;
-use syn::{punctuated::Punctuated, DeriveInput};
+use syn::{punctuated::Punctuated, Attribute, DeriveInput, Lit, Meta, NestedMeta};
 
 pub fn crate_path(input: &DeriveInput) -> TokenStream {
     let path = input.attrs.iter().find_map(|a| {
@@ -47,3 +47,69 @@ pub fn crate_path(input: &DeriveInput) -> TokenStream {
     });
     path.unwrap_or_else(|| quote! {automergeable})
 }
+
+/// Find the value of a `#[automergeable(name = "value")]` attribute on a field.
+pub fn field_attribute(attrs: &[Attribute], name: &str) -> Option<String> {
+    let mut value = None;
+    for a in attrs {
+        match a.parse_meta().unwrap() {
+            Meta::NameValue(_) | Meta::Path(_) => {}
+            Meta::List(meta) => {
+                if Some("automergeable".to_owned())
+                    == meta.path.get_ident().map(ToString::to_string)
+                {
+                    for m in meta.nested {
+                        match m {
+                            NestedMeta::Meta(meta) => match meta {
+                                Meta::Path(_) | Meta::List(_) => {}
+                                Meta::NameValue(n) => {
+                                    if let (true, Lit::Str(lit)) = (n.path.is_ident(name), &n.lit) {
+                                        value = Some(lit.value())
+                                    }
+                                }
+                            },
+                            NestedMeta::Lit(_) => {}
+                        }
+                    }
+                }
+            }
+        }
+    }
+    value
+}
+
+/// Find the value of the `#[automergeable(version = N)]` attribute on a type.
+///
+/// The version must be an integer literal of at least 1, as values without a version are version 1.
+pub fn version(input: &DeriveInput) -> syn::Result<Option<u64>> {
+    let mut version = None;
+    for a in &input.attrs {
+        if let Ok(Meta::List(meta)) = a.parse_meta() {
+            if meta.path.is_ident("automergeable") {
+                for m in meta.nested {
+                    if let NestedMeta::Meta(Meta::NameValue(n)) = m {
+                        if n.path.is_ident("version") {
+                            let v = match &n.lit {
+                                Lit::Int(i) => i.base10_parse::<u64>()?,
+                                lit => {
+                                    return Err(syn::Error::new_spanned(
+                                        lit,
+                                        "the version must be an integer, such as `version = 2`",
+                                    ))
+                                }
+                            };
+                            if v == 0 {
+                                return Err(syn::Error::new_spanned(
+                                    &n.lit,
+                                    "the version must be at least 1",
+                                ));
+                            }
+                            version = Some(v)
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(version)
+}
@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+
+use automerge::{Path, Primitive, Value};
+use automergeable::{Diff, ToAutomerge};
+use maplit::hashmap;
+use pretty_assertions::assert_eq;
+
+#[test]
+fn diff_struct_fields() {
+    #[derive(ToAutomerge, Diff, Debug, Clone, Default)]
+    struct A {
+        a: u64,
+        b: B,
+        map: HashMap<String, u64>,
+        #[automergeable(representation = "counter")]
+        counter: i64,
+    }
+
+    #[derive(ToAutomerge, Diff, Debug, Clone, Default)]
+    struct B {
+        inner: u64,
+        other: u64,
+    }
+
+    let old = A {
+        map: hashmap! {"x".to_owned() => 1, "y".to_owned() => 2},
+        ..A::default()
+    };
+    let mut new = old.clone();
+    new.b.inner = 3;
+    new.map.remove("x");
+    new.map.insert("z".to_owned(), 4);
+    new.counter = 2;
+
+    let mut changed = Vec::new();
+    new.changed_values(&old, Path::root(), &mut changed);
+    changed.sort_by_key(|(path, _)| path.to_string());
+    assert_eq!(
+        changed,
+        vec![
+            (
+                Path::root().key("b").key("inner"),
+                Some(Value::Primitive(Primitive::Uint(3)))
+            ),
+            (
+                Path::root().key("counter"),
+                Some(Value::Primitive(Primitive::Counter(2)))
+            ),
+            (Path::root().key("map").key("x"), None),
+            (
+                Path::root().key("map").key("z"),
+                Some(Value::Primitive(Primitive::Uint(4)))
+            ),
+        ]
+    );
+}
+
+#[test]
+fn diff_enum_variants() {
+    #[derive(ToAutomerge, Diff, Debug, Clone)]
+    enum E {
+        A { x: u64, y: u64 },
+        B(u64, u64),
+        C,
+    }
+
+    let mut changed = Vec::new();
+    E::A { x: 1, y: 2 }.changed_values(&E::A { x: 1, y: 3 }, Path::root(), &mut changed);
+    assert_eq!(
+        changed,
+        vec![(
+            Path::root().key("A").key("y"),
+            Some(Value::Primitive(Primitive::Uint(2)))
+        )]
+    );
+
+    let mut changed = Vec::new();
+    E::B(1, 2).changed_values(&E::B(0, 2), Path::root(), &mut changed);
+    assert_eq!(
+        changed,
+        vec![(
+            Path::root().key("B").index(0),
+            Some(Value::Primitive(Primitive::Uint(1)))
+        )]
+    );
+
+    let mut changed = Vec::new();
+    E::C.changed_values(&E::B(0, 2), Path::root(), &mut changed);
+    assert_eq!(changed, vec![(Path::root(), Some(E::C.to_automerge()))]);
+}
+
+#[test]
+fn diff_list_elements() {
+    #[derive(ToAutomerge, Diff, Debug, Clone, Default)]
+    struct A {
+        list: Vec<B>,
+    }
+
+    #[derive(ToAutomerge, Diff, Debug, Clone, Default)]
+    struct B {
+        inner: u64,
+        other: u64,
+    }
+
+    let old = A {
+        list: vec![B::default(), B::default()],
+    };
+    let mut new = old.clone();
+    new.list[1].inner = 3;
+
+    // lists of the same length have their elements diffed
+    let mut changed = Vec::new();
+    new.changed_values(&old, Path::root(), &mut changed);
+    assert_eq!(
+        changed,
+        vec![(
+            Path::root().key("list").index(1).key("inner"),
+            Some(Value::Primitive(Primitive::Uint(3)))
+        )]
+    );
+
+    // otherwise the whole list is changed
+    new.list.pop();
+    let mut changed = Vec::new();
+    new.changed_values(&old, Path::root(), &mut changed);
+    assert_eq!(
+        changed,
+        vec![(Path::root().key("list"), Some(new.list.to_automerge()))]
+    );
+}
@@ -439,3 +439,36 @@ fn multi_generics() {
         B::from_automerge(&B::<u32, String>::D(String::new()).to_automerge()).unwrap()
     );
 }
+
+#[test]
+fn tables() {
+    #[derive(ToAutomerge, FromAutomerge, PartialEq, Debug, Clone, Default)]
+    struct A {
+        rows: automergeable::Table<B>,
+        #[automergeable(representation = "table")]
+        named: HashMap<String, B>,
+    }
+
+    #[derive(ToAutomerge, FromAutomerge, PartialEq, Debug, Clone, Default)]
+    struct B {
+        inner: u64,
+    }
+
+    let mut a = A::default();
+    let id = a.rows.insert(B { inner: 1 });
+    let other = a.rows.insert(B { inner: 2 });
+    a.named.insert("b".to_owned(), B { inner: 3 });
+    assert_eq!(a.rows.len(), 2);
+    assert_eq!(a.rows.remove(&other), Some(B { inner: 2 }));
+
+    let value = a.to_automerge();
+    if let automerge::Value::Map(map) = &value {
+        assert!(matches!(map["rows"], automerge::Value::Table(_)));
+        assert!(matches!(map["named"], automerge::Value::Table(_)));
+    } else {
+        panic!("expected a map, found {:?}", value)
+    }
+    let from = A::from_automerge(&value).unwrap();
+    assert_eq!(from.rows.get(&id), Some(&B { inner: 1 }));
+    assert_eq!(from, a);
+}
@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 
 use automerge::{Primitive, Value};
-use automergeable::{Automergeable, ToAutomerge};
+use automergeable::{Automergeable, KeyPathElement, ListKeys, ToAutomerge};
 use insta::{assert_json_snapshot, Settings};
 use maplit::hashmap;
 use pretty_assertions::assert_eq;
@@ -532,3 +532,36 @@ fn multi_generics() {
         B::<u32, String>::D(String::new()).to_automerge()
     );
 }
+
+#[test]
+fn list_keys() {
+    #[derive(ToAutomerge)]
+    struct Item {
+        id: u64,
+    }
+
+    #[derive(ToAutomerge)]
+    struct A {
+        #[automergeable(key = "id")]
+        items: Vec<Item>,
+        nested: Vec<B>,
+    }
+
+    #[derive(ToAutomerge)]
+    struct B {
+        #[automergeable(key = "id")]
+        items: Vec<Item>,
+    }
+
+    let mut keys = ListKeys::new();
+    keys.insert(vec![KeyPathElement::Key("items".into())], "id");
+    keys.insert(
+        vec![
+            KeyPathElement::Key("nested".into()),
+            KeyPathElement::AnyIndex,
+            KeyPathElement::Key("items".into()),
+        ],
+        "id",
+    );
+    assert_eq!(keys, A::list_keys());
+}
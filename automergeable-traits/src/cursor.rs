@@ -0,0 +1,56 @@
+use automerge::{Path, Primitive, Value};
+
+use crate::{ChangedValues, Diff, FromAutomerge, FromAutomergeError, ToAutomerge};
+
+/// A position in some text which follows the character it points at as the text is changed.
+///
+/// Cursors can only be created from the text in a document, such as with
+/// `Document::change_with_cursors`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor(automerge_frontend::Cursor);
+
+impl Cursor {
+    /// The index of the character the cursor points at, as of the last time the value was read
+    /// from the document.
+    pub const fn index(&self) -> u32 {
+        self.0.index
+    }
+
+    /// Get the underlying automerge cursor.
+    pub fn into_inner(self) -> automerge_frontend::Cursor {
+        self.0
+    }
+}
+
+impl From<automerge_frontend::Cursor> for Cursor {
+    fn from(cursor: automerge_frontend::Cursor) -> Self {
+        Self(cursor)
+    }
+}
+
+impl ToAutomerge for Cursor {
+    fn to_automerge(&self) -> Value {
+        Value::Primitive(Primitive::Cursor(self.0.clone()))
+    }
+}
+
+impl FromAutomerge for Cursor {
+    fn from_automerge(value: &Value) -> Result<Self, FromAutomergeError> {
+        if let Value::Primitive(Primitive::Cursor(cursor)) = value {
+            Ok(Self(cursor.clone()))
+        } else {
+            Err(FromAutomergeError::WrongType {
+                found: value.clone(),
+                expected: "a primitive cursor".to_owned(),
+            })
+        }
+    }
+}
+
+impl Diff for Cursor {
+    fn changed_values(&self, old: &Self, path: Path, changed: &mut ChangedValues) {
+        if self != old {
+            changed.push((path, Some(self.to_automerge())))
+        }
+    }
+}
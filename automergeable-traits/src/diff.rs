@@ -0,0 +1,173 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    rc::Rc,
+    sync::Arc,
+};
+
+use automerge::{Path, Value};
+
+use crate::ToAutomerge;
+
+/// The paths which changed between two values, along with their new values.
+///
+/// A value of `None` means the value at the path was removed.
+pub type ChangedValues = Vec<(Path, Option<Value>)>;
+
+/// Find the parts of a value which changed, without converting the whole value.
+///
+/// This lets a change to a large value be converted and diffed in time proportional to the size
+/// of the change, rather than the size of the value.
+pub trait Diff: ToAutomerge {
+    /// Push the path and new value of each part of `self` which differs from `old`, where `self`
+    /// is at `path`.
+    ///
+    /// Parts which aren't split up further are pushed whole, so should be diffed against the old
+    /// value at their path.
+    fn changed_values(&self, old: &Self, path: Path, changed: &mut ChangedValues);
+}
+
+macro_rules! whole_value_diff {
+    ( $( $x:ty ),* $(,)? ) => {
+        $(
+        impl Diff for $x {
+            fn changed_values(&self, old: &Self, path: Path, changed: &mut ChangedValues) {
+                if self != old {
+                    changed.push((path, Some(self.to_automerge())))
+                }
+            }
+        })*
+    };
+}
+
+whole_value_diff! {
+    Value,
+    (),
+    String,
+    char,
+    f64,
+    bool,
+    i8,
+    i16,
+    i32,
+    i64,
+    isize,
+    i128,
+    u8,
+    u16,
+    u32,
+    u64,
+    usize,
+    u128,
+    std::num::NonZeroI8,
+    std::num::NonZeroI16,
+    std::num::NonZeroI32,
+    std::num::NonZeroI64,
+    std::num::NonZeroI128,
+    std::num::NonZeroIsize,
+    std::num::NonZeroU8,
+    std::num::NonZeroU16,
+    std::num::NonZeroU32,
+    std::num::NonZeroU64,
+    std::num::NonZeroU128,
+    std::num::NonZeroUsize,
+    serde_json::Value,
+}
+
+#[cfg(feature = "std")]
+whole_value_diff! {
+    std::time::SystemTime,
+}
+
+// lists of the same length are diffed element by element, so reordered elements are updated in
+// place rather than moved, otherwise the whole list is pushed so that insertions and deletions can
+// be found
+impl<T> Diff for Vec<T>
+where
+    T: Diff,
+{
+    fn changed_values(&self, old: &Self, path: Path, changed: &mut ChangedValues) {
+        if self.len() == old.len() {
+            for (i, (v, old_v)) in self.iter().zip(old).enumerate() {
+                v.changed_values(old_v, path.clone().index(i as u32), changed)
+            }
+        } else {
+            changed.push((path, Some(self.to_automerge())))
+        }
+    }
+}
+
+impl<K, V> Diff for HashMap<K, V>
+where
+    K: ToString + Eq + Hash,
+    V: Diff,
+{
+    fn changed_values(&self, old: &Self, path: Path, changed: &mut ChangedValues) {
+        for (k, v) in self {
+            let path = path.clone().key(k.to_string());
+            match old.get(k) {
+                Some(old_v) => v.changed_values(old_v, path, changed),
+                None => changed.push((path, Some(v.to_automerge()))),
+            }
+        }
+        for k in old.keys() {
+            if !self.contains_key(k) {
+                changed.push((path.clone().key(k.to_string()), None))
+            }
+        }
+    }
+}
+
+impl<K, V> Diff for BTreeMap<K, V>
+where
+    K: ToString + Ord,
+    V: Diff,
+{
+    fn changed_values(&self, old: &Self, path: Path, changed: &mut ChangedValues) {
+        for (k, v) in self {
+            let path = path.clone().key(k.to_string());
+            match old.get(k) {
+                Some(old_v) => v.changed_values(old_v, path, changed),
+                None => changed.push((path, Some(v.to_automerge()))),
+            }
+        }
+        for k in old.keys() {
+            if !self.contains_key(k) {
+                changed.push((path.clone().key(k.to_string()), None))
+            }
+        }
+    }
+}
+
+impl<T> Diff for Option<T>
+where
+    T: Diff,
+{
+    fn changed_values(&self, old: &Self, path: Path, changed: &mut ChangedValues) {
+        match (self, old) {
+            (Some(new), Some(old)) => new.changed_values(old, path, changed),
+            (None, None) => {}
+            _ => changed.push((path, Some(self.to_automerge()))),
+        }
+    }
+}
+
+macro_rules! refs {
+    ( $( $x:ty ),* $(,)? ) => {
+        $(
+        impl<T> Diff for $x
+        where
+            T: Diff,
+        {
+            fn changed_values(&self, old: &Self, path: Path, changed: &mut ChangedValues) {
+                (**self).changed_values(&**old, path, changed)
+            }
+        })*
+    };
+}
+
+refs! {
+    Box<T>,
+    Rc<T>,
+    Arc<T>,
+}
@@ -12,9 +12,23 @@ use automerge::{Primitive, Value};
 use serde_json::Number;
 use smol_str::SmolStr;
 
+/// The key of the schema version marker in the maps of versioned types.
+pub const VERSION_KEY: &str = "__version";
+
 /// Require a method to convert to a value from an automerge value.
 pub trait FromAutomerge: Sized {
     fn from_automerge(value: &Value) -> Result<Self, FromAutomergeError>;
+
+    /// The schema version of this type, stored under [`VERSION_KEY`], if values stored with older
+    /// versions should be migrated.
+    fn version() -> Option<u64> {
+        None
+    }
+
+    /// Migrate a value stored with schema `version` to `version + 1`.
+    fn migrate(_version: u64, value: Value) -> Value {
+        value
+    }
 }
 
 /// A failure converting from an automerge value to Rust type.
@@ -29,6 +43,8 @@ pub enum FromAutomergeError {
     FailedTryFrom,
     #[error("unknown error: {0}")]
     Unknown(#[from] Box<dyn Error + Send + Sync>),
+    #[error(transparent)]
+    MigrationError(#[from] crate::MigrationError),
 }
 
 impl FromAutomerge for Value {
@@ -328,18 +344,18 @@ impl FromAutomerge for serde_json::Value {
         let var_name = match value {
             Value::Map(map) => Ok(Self::Object(
                 map.iter()
-                    .map(|(k, v)| (k.to_string(), Self::from_automerge(v).unwrap()))
-                    .collect(),
+                    .map(|(k, v)| Ok((k.to_string(), Self::from_automerge(v)?)))
+                    .collect::<Result<_, FromAutomergeError>>()?,
             )),
             Value::Table(map) => Ok(Self::Object(
                 map.iter()
-                    .map(|(k, v)| (k.to_string(), Self::from_automerge(v).unwrap()))
-                    .collect(),
+                    .map(|(k, v)| Ok((k.to_string(), Self::from_automerge(v)?)))
+                    .collect::<Result<_, FromAutomergeError>>()?,
             )),
             Value::List(v) => Ok(Self::Array(
                 v.iter()
-                    .map(|i| Self::from_automerge(i).unwrap())
-                    .collect::<Vec<_>>(),
+                    .map(Self::from_automerge)
+                    .collect::<Result<Vec<_>, _>>()?,
             )),
             Value::Text(v) => Ok(Self::String(v.concat())),
             Value::Primitive(p) => match p {
@@ -352,9 +368,10 @@ impl FromAutomerge for serde_json::Value {
                 Primitive::F64(f) => Ok(Self::Number(Number::from_f64(*f).unwrap())),
                 Primitive::Timestamp(i) => Ok(Self::Number(Number::from(*i))),
                 Primitive::Boolean(b) => Ok(Self::Bool(*b)),
-                Primitive::Cursor(_) => {
-                    panic!("cursor is unsupported")
-                }
+                Primitive::Cursor(_) => Err(FromAutomergeError::WrongType {
+                    found: value.clone(),
+                    expected: "a value with a JSON representation".to_owned(),
+                }),
                 Primitive::Null => Ok(Self::Null),
             },
         };
@@ -0,0 +1,80 @@
+use automerge::{Path, PathElement};
+use smol_str::SmolStr;
+
+/// An element of a pattern matching the paths of lists in a value.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum KeyPathElement {
+    /// A specific key in a map.
+    Key(SmolStr),
+    /// A specific index in a list.
+    Index(u32),
+    /// Any key in a map.
+    AnyKey,
+    /// Any index in a list.
+    AnyIndex,
+}
+
+impl KeyPathElement {
+    fn matches(&self, element: &PathElement) -> bool {
+        match (self, element) {
+            (Self::Key(k), PathElement::Key(key)) => k.as_str() == key.as_str(),
+            (Self::Index(i), PathElement::Index(index)) => i == index,
+            (Self::AnyKey, PathElement::Key(_)) | (Self::AnyIndex, PathElement::Index(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+/// The fields which identify the elements of lists, by the path to each list.
+///
+/// Lists of maps with a key can then be diffed by matching up the old and new elements by the
+/// value of that field, rather than by their position.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ListKeys(Vec<(Vec<KeyPathElement>, SmolStr)>);
+
+impl ListKeys {
+    /// Create a new, empty, set of list keys.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether there are no keys.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Use the field `key` to identify elements of the lists matching `path`.
+    pub fn insert<K: Into<SmolStr>>(&mut self, path: Vec<KeyPathElement>, key: K) {
+        self.0.push((path, key.into()))
+    }
+
+    /// Add all of the keys from `other`.
+    pub fn merge(&mut self, other: Self) {
+        self.0.extend(other.0)
+    }
+
+    /// Add the keys of a nested value, found under `prefix`.
+    pub fn extend_nested(&mut self, prefix: KeyPathElement, nested: Self) {
+        self.0.extend(nested.0.into_iter().map(|(mut path, key)| {
+            path.insert(0, prefix.clone());
+            (path, key)
+        }))
+    }
+
+    /// Get the key for the list at the given path, if there is one.
+    pub fn get(&self, path: &Path) -> Option<&SmolStr> {
+        if self.is_empty() {
+            return None;
+        }
+        let elements = path.clone().elements();
+        self.0.iter().find_map(|(pattern, key)| {
+            if pattern.len() == elements.len()
+                && pattern.iter().zip(&elements).all(|(p, e)| p.matches(e))
+            {
+                Some(key)
+            } else {
+                None
+            }
+        })
+    }
+}
@@ -1,10 +1,22 @@
 // #![warn(clippy::pedantic)]
 #![warn(clippy::nursery)]
 
+mod cursor;
+mod diff;
 mod from;
+mod keys;
+mod migrate;
+mod table;
 mod to;
 
-pub use from::{FromAutomerge, FromAutomergeError, Text};
+pub use cursor::Cursor;
+pub use diff::{ChangedValues, Diff};
+pub use from::{FromAutomerge, FromAutomergeError, Text, VERSION_KEY};
+pub use keys::{KeyPathElement, ListKeys};
+pub use migrate::{migrate, MigrationError};
+pub use table::Table;
+#[doc(hidden)]
+pub use table::{map_to_table, table_to_map};
 pub use to::ToAutomerge;
 
 /// Overall trait for requiring all automerge sub-traits.
@@ -0,0 +1,59 @@
+use automerge::{Primitive, Value};
+
+use crate::{FromAutomerge, VERSION_KEY};
+
+/// An error type for migrating values between schema versions.
+#[derive(Debug, thiserror::Error)]
+pub enum MigrationError {
+    /// The version marker wasn't an unsigned integer.
+    #[error("invalid version marker: {0:?}")]
+    InvalidVersion(Value),
+    /// The value was stored with a newer version than the type knows about.
+    #[error("found version {found} which is newer than the current version {current}")]
+    NewerVersion {
+        /// The version of the value.
+        found: u64,
+        /// The version of the type.
+        current: u64,
+    },
+}
+
+/// Migrate a value stored with an older schema version of `T` to the current one.
+///
+/// Values without a version marker are version 1, apart from empty maps which are taken to be new.
+/// Returns `Ok(None)` if the value is already current or `T` isn't versioned.
+pub fn migrate<T>(value: &Value) -> Result<Option<Value>, MigrationError>
+where
+    T: FromAutomerge,
+{
+    let current = match T::version() {
+        Some(current) => current,
+        None => return Ok(None),
+    };
+    let found = match value {
+        Value::Map(map) if map.is_empty() => return Ok(None),
+        Value::Map(map) => match map.get(VERSION_KEY) {
+            Some(Value::Primitive(Primitive::Uint(version))) => *version,
+            Some(version) => return Err(MigrationError::InvalidVersion(version.clone())),
+            None => 1,
+        },
+        _ => 1,
+    };
+    if found > current {
+        return Err(MigrationError::NewerVersion { found, current });
+    }
+    if found == current {
+        return Ok(None);
+    }
+    let mut value = value.clone();
+    for version in found..current {
+        value = T::migrate(version, value)
+    }
+    if let Value::Map(map) = &mut value {
+        map.insert(
+            VERSION_KEY.into(),
+            Value::Primitive(Primitive::Uint(current)),
+        );
+    }
+    Ok(Some(value))
+}
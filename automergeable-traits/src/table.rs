@@ -0,0 +1,136 @@
+use std::{collections::HashMap, str::FromStr};
+
+use automerge::{Path, Value};
+use uuid::Uuid;
+
+use crate::{
+    ChangedValues, Diff, FromAutomerge, FromAutomergeError, KeyPathElement, ListKeys, ToAutomerge,
+};
+
+/// A collection of rows keyed by generated UUIDs, stored as an automerge table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table<T> {
+    rows: HashMap<Uuid, T>,
+}
+
+impl<T> Table<T> {
+    /// Create a new, empty table.
+    pub fn new() -> Self {
+        Self {
+            rows: HashMap::new(),
+        }
+    }
+
+    /// Insert a row, returning the id generated for it.
+    pub fn insert(&mut self, row: T) -> Uuid {
+        let id = Uuid::new_v4();
+        self.rows.insert(id, row);
+        id
+    }
+
+    /// Remove the row with the id, returning it if there was one.
+    pub fn remove(&mut self, id: &Uuid) -> Option<T> {
+        self.rows.remove(id)
+    }
+
+    /// Get the row with the id.
+    pub fn get(&self, id: &Uuid) -> Option<&T> {
+        self.rows.get(id)
+    }
+
+    /// Get the row with the id mutably.
+    pub fn get_mut(&mut self, id: &Uuid) -> Option<&mut T> {
+        self.rows.get_mut(id)
+    }
+
+    /// The number of rows in the table.
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Whether the table has no rows.
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Iterate over the ids and rows of the table, in an arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (&Uuid, &T)> {
+        self.rows.iter()
+    }
+}
+
+impl<T> Default for Table<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> ToAutomerge for Table<T>
+where
+    T: ToAutomerge,
+{
+    fn to_automerge(&self) -> Value {
+        Value::Table(
+            self.rows
+                .iter()
+                .map(|(id, row)| (id.to_string().into(), row.to_automerge()))
+                .collect(),
+        )
+    }
+
+    fn list_keys() -> ListKeys {
+        let mut keys = ListKeys::new();
+        keys.extend_nested(KeyPathElement::AnyKey, T::list_keys());
+        keys
+    }
+}
+
+impl<T> FromAutomerge for Table<T>
+where
+    T: FromAutomerge,
+{
+    fn from_automerge(value: &Value) -> Result<Self, FromAutomergeError> {
+        match value {
+            // maps are accepted too so that tables can have `representation = "table"`
+            Value::Table(map) | Value::Map(map) => {
+                let mut rows = HashMap::with_capacity(map.len());
+                for (id, row) in map {
+                    let id = Uuid::from_str(id).map_err(|_| FromAutomergeError::FailedTryFrom)?;
+                    rows.insert(id, T::from_automerge(row)?);
+                }
+                Ok(Self { rows })
+            }
+            _ => Err(FromAutomergeError::WrongType {
+                found: value.clone(),
+                expected: "a table".to_owned(),
+            }),
+        }
+    }
+}
+
+impl<T> Diff for Table<T>
+where
+    T: Diff,
+{
+    fn changed_values(&self, old: &Self, path: Path, changed: &mut ChangedValues) {
+        self.rows.changed_values(&old.rows, path, changed)
+    }
+}
+
+/// Store a map as a table, for fields with `representation = "table"`.
+#[doc(hidden)]
+pub fn map_to_table(value: Value) -> Value {
+    match value {
+        Value::Map(map) => Value::Table(map),
+        value => value,
+    }
+}
+
+/// Read a table as a map, for fields with `representation = "table"`.
+#[doc(hidden)]
+pub fn table_to_map(value: &Value) -> Value {
+    match value {
+        Value::Table(map) => Value::Map(map.clone()),
+        value => value.clone(),
+    }
+}
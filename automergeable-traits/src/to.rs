@@ -8,9 +8,19 @@ use std::{
 use automerge::{Primitive, Value};
 use smol_str::SmolStr;
 
+use crate::{KeyPathElement, ListKeys};
+
 /// Require a method to convert the current value into an automerge value.
 pub trait ToAutomerge {
     fn to_automerge(&self) -> Value;
+
+    /// The fields identifying the elements of lists within this type, used when diffing them.
+    fn list_keys() -> ListKeys
+    where
+        Self: Sized,
+    {
+        ListKeys::new()
+    }
 }
 
 impl ToAutomerge for Value {
@@ -33,6 +43,12 @@ where
         let vals = self.iter().map(|v| v.to_automerge()).collect::<Vec<_>>();
         Value::List(vals)
     }
+
+    fn list_keys() -> ListKeys {
+        let mut keys = ListKeys::new();
+        keys.extend_nested(KeyPathElement::AnyIndex, T::list_keys());
+        keys
+    }
 }
 
 // impl<T> ToAutomerge for HashSet<T>
@@ -57,6 +73,11 @@ where
         }
         Value::Map(hm)
     }
+    fn list_keys() -> ListKeys {
+        let mut keys = ListKeys::new();
+        keys.extend_nested(KeyPathElement::AnyKey, V::list_keys());
+        keys
+    }
 }
 
 impl<K, V> ToAutomerge for BTreeMap<K, V>
@@ -71,6 +92,11 @@ where
         }
         Value::Map(hm)
     }
+    fn list_keys() -> ListKeys {
+        let mut keys = ListKeys::new();
+        keys.extend_nested(KeyPathElement::AnyKey, V::list_keys());
+        keys
+    }
 }
 
 impl ToAutomerge for String {
@@ -105,6 +131,9 @@ where
         self.as_ref()
             .map_or(Value::Primitive(Primitive::Null), |v| v.to_automerge())
     }
+    fn list_keys() -> ListKeys {
+        T::list_keys()
+    }
 }
 
 #[cfg(feature = "std")]
@@ -251,6 +280,10 @@ macro_rules! refs {
             fn to_automerge(&self) -> Value {
                 (**self).to_automerge()
             }
+
+            fn list_keys() -> ListKeys {
+                T::list_keys()
+            }
         })*
     };
 }
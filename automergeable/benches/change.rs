@@ -30,6 +30,19 @@ fn bench_many(mut document: Document<HashMap<String, String>, Frontend>, limit:
     });
 }
 
+fn bench_many_incremental(mut document: Document<HashMap<String, String>, Frontend>, limit: usize) {
+    #[allow(clippy::unit_arg)]
+    black_box(for i in 0..limit {
+        document
+            .change_incremental::<_, _, std::convert::Infallible>(|doc| {
+                doc.insert(i.to_string(), i.to_string());
+
+                Ok(())
+            })
+            .unwrap();
+    });
+}
+
 fn bench_changes_single(c: &mut Criterion) {
     for &i in &[100, 1000, 10000] {
         c.bench_function(&format!("change with {} entries, single change", i), |b| {
@@ -54,5 +67,25 @@ fn bench_changes_many(c: &mut Criterion) {
     }
 }
 
-criterion_group!(benches, bench_changes_single, bench_changes_many);
+fn bench_changes_many_incremental(c: &mut Criterion) {
+    for &i in &[100, 1000, 10000] {
+        c.bench_function(
+            &format!("change with {} entries, many incremental changes", i),
+            |b| {
+                b.iter_batched(
+                    || Document::<HashMap<String, String>, Frontend>::new(Frontend::new()),
+                    |doc| bench_many_incremental(doc, i),
+                    criterion::BatchSize::SmallInput,
+                )
+            },
+        );
+    }
+}
+
+criterion_group!(
+    benches,
+    bench_changes_single,
+    bench_changes_many,
+    bench_changes_many_incremental
+);
 criterion_main!(benches);
@@ -0,0 +1,279 @@
+use std::convert::TryInto;
+
+use automerge::{LocalChange, LocalOperation, Path, PathElement, Primitive, Value};
+use smol_str::SmolStr;
+
+/// An error type for applying changes to a value.
+#[derive(Debug, thiserror::Error)]
+pub enum ApplyChangeError {
+    /// There is no value at the path.
+    #[error("no value at {path}")]
+    NoSuchPath {
+        /// The path which doesn't exist.
+        path: Path,
+    },
+    /// The value at the path is a primitive so can't contain other values.
+    #[error("the value at {path} is not a map, table, list or text")]
+    NotAContainer {
+        /// The path to the value.
+        path: Path,
+    },
+    /// Values can only be inserted into lists and text.
+    #[error("the value at {path} is not a list or text")]
+    NotASequence {
+        /// The path to the value.
+        path: Path,
+    },
+    /// Only counters can be incremented.
+    #[error("the value at {path} is not a counter")]
+    NotACounter {
+        /// The path to the value.
+        path: Path,
+    },
+    /// Counters can only be incremented or deleted.
+    #[error("cannot overwrite the counter at {path}")]
+    CannotOverwriteCounter {
+        /// The path to the counter.
+        path: Path,
+    },
+    /// Text can only contain strings.
+    #[error("cannot put a non string value into the text at {path}")]
+    NonStringInText {
+        /// The path to the text.
+        path: Path,
+    },
+    /// The root can only be a map.
+    #[error("cannot replace the root with a non map value")]
+    NonMapRoot,
+}
+
+/// Apply the changes to the value, in order, without needing a frontend.
+///
+/// This interprets the changes in the same way as an automerge frontend would, so it can be used
+/// to preview the result of a set of changes. The value may be partially changed if an error is
+/// returned.
+pub fn apply_changes(value: &mut Value, changes: &[LocalChange]) -> Result<(), ApplyChangeError> {
+    for change in changes {
+        apply_change(value, change)?
+    }
+    Ok(())
+}
+
+/// Apply a single change to the value.
+pub fn apply_change(value: &mut Value, change: &LocalChange) -> Result<(), ApplyChangeError> {
+    let path = change.path();
+    let elements = path.clone().elements();
+    let (last, parent_elements) = match elements.split_last() {
+        Some(split) => split,
+        // the root has no parent so can only be replaced with another map or incremented
+        None => {
+            return match (change.operation(), value) {
+                (LocalOperation::Set(_), Value::Primitive(Primitive::Counter(_))) => {
+                    Err(ApplyChangeError::CannotOverwriteCounter { path: path.clone() })
+                }
+                (LocalOperation::Set(new @ Value::Map(_)), value) => {
+                    *value = new.clone();
+                    Ok(())
+                }
+                (LocalOperation::Increment(by), Value::Primitive(Primitive::Counter(c))) => {
+                    *c = c.wrapping_add(*by);
+                    Ok(())
+                }
+                (LocalOperation::Increment(_), _) => {
+                    Err(ApplyChangeError::NotACounter { path: path.clone() })
+                }
+                (LocalOperation::Set(_), _) => Err(ApplyChangeError::NonMapRoot),
+                _ => Err(ApplyChangeError::NoSuchPath { path: path.clone() }),
+            }
+        }
+    };
+    let parent_path = path.clone().parent();
+    let parent =
+        resolve_mut(value, parent_elements).ok_or_else(|| ApplyChangeError::NoSuchPath {
+            path: parent_path.clone(),
+        })?;
+    let no_such_path = || ApplyChangeError::NoSuchPath { path: path.clone() };
+
+    match change.operation() {
+        LocalOperation::Set(new) => {
+            let old = match (parent, last) {
+                (Value::Map(map), PathElement::Key(k))
+                | (Value::Table(map), PathElement::Key(k)) => map
+                    .entry(SmolStr::new(k))
+                    .or_insert(Value::Primitive(Primitive::Null)),
+                (Value::List(vec), PathElement::Index(i)) => {
+                    vec.get_mut(index(*i)).ok_or_else(no_such_path)?
+                }
+                (Value::Text(graphemes), PathElement::Index(i)) => {
+                    let grapheme = graphemes.get_mut(index(*i)).ok_or_else(no_such_path)?;
+                    *grapheme = text_grapheme(new, &parent_path)?;
+                    return Ok(());
+                }
+                (Value::Primitive(_), _) => {
+                    return Err(ApplyChangeError::NotAContainer { path: parent_path })
+                }
+                _ => return Err(no_such_path()),
+            };
+            if let Value::Primitive(Primitive::Counter(_)) = old {
+                return Err(ApplyChangeError::CannotOverwriteCounter { path: path.clone() });
+            }
+            *old = new.clone()
+        }
+        LocalOperation::Insert(new) => match (parent, last) {
+            (Value::List(vec), PathElement::Index(i)) if index(*i) <= vec.len() => {
+                vec.insert(index(*i), new.clone())
+            }
+            (Value::Text(graphemes), PathElement::Index(i)) if index(*i) <= graphemes.len() => {
+                graphemes.insert(index(*i), text_grapheme(new, &parent_path)?)
+            }
+            (Value::List(_), _) | (Value::Text(_), _) => return Err(no_such_path()),
+            _ => return Err(ApplyChangeError::NotASequence { path: parent_path }),
+        },
+        LocalOperation::Delete => match (parent, last) {
+            (Value::Map(map), PathElement::Key(k)) | (Value::Table(map), PathElement::Key(k)) => {
+                map.remove(k.as_str()).ok_or_else(no_such_path)?;
+            }
+            (Value::List(vec), PathElement::Index(i)) if index(*i) < vec.len() => {
+                vec.remove(index(*i));
+            }
+            (Value::Text(graphemes), PathElement::Index(i)) if index(*i) < graphemes.len() => {
+                graphemes.remove(index(*i));
+            }
+            (Value::Primitive(_), _) => {
+                return Err(ApplyChangeError::NotAContainer { path: parent_path })
+            }
+            _ => return Err(no_such_path()),
+        },
+        LocalOperation::Increment(by) => {
+            match resolve_mut(parent, std::slice::from_ref(last)).ok_or_else(no_such_path)? {
+                Value::Primitive(Primitive::Counter(c)) => *c = c.wrapping_add(*by),
+                _ => return Err(ApplyChangeError::NotACounter { path: path.clone() }),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn index(i: u32) -> usize {
+    i.try_into().unwrap()
+}
+
+/// Find the value at the path.
+pub fn resolve<'a>(value: &'a Value, elements: &[PathElement]) -> Option<&'a Value> {
+    elements
+        .iter()
+        .try_fold(value, |value, element| match (value, element) {
+            (Value::Map(map), PathElement::Key(k)) | (Value::Table(map), PathElement::Key(k)) => {
+                map.get(k.as_str())
+            }
+            (Value::List(vec), PathElement::Index(i)) => vec.get(index(*i)),
+            _ => None,
+        })
+}
+
+/// Find the value at the path, mutably.
+fn resolve_mut<'a>(value: &'a mut Value, elements: &[PathElement]) -> Option<&'a mut Value> {
+    elements
+        .iter()
+        .try_fold(value, |value, element| match (value, element) {
+            (Value::Map(map), PathElement::Key(k)) | (Value::Table(map), PathElement::Key(k)) => {
+                map.get_mut(k.as_str())
+            }
+            (Value::List(vec), PathElement::Index(i)) => vec.get_mut(index(*i)),
+            _ => None,
+        })
+}
+
+fn text_grapheme(value: &Value, path: &Path) -> Result<SmolStr, ApplyChangeError> {
+    if let Value::Primitive(Primitive::Str(s)) = value {
+        Ok(s.clone())
+    } else {
+        Err(ApplyChangeError::NonStringInText { path: path.clone() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn apply_to_nested_values() {
+        let mut value = Value::Map(HashMap::new());
+        let changes = vec![
+            LocalChange::set(Path::root().key("l"), Value::List(Vec::new())),
+            LocalChange::insert(
+                Path::root().key("l").index(0),
+                Value::Primitive(Primitive::Counter(1)),
+            ),
+            LocalChange::increment_by(Path::root().key("l").index(0), 2),
+            LocalChange::set(Path::root().key("t"), Value::Text(vec!["a".into()])),
+            LocalChange::insert(
+                Path::root().key("t").index(1),
+                Value::Primitive(Primitive::Str("b".into())),
+            ),
+            LocalChange::delete(Path::root().key("t").index(0)),
+        ];
+        apply_changes(&mut value, &changes).unwrap();
+
+        let mut expected = HashMap::new();
+        expected.insert(
+            "l".into(),
+            Value::List(vec![Value::Primitive(Primitive::Counter(3))]),
+        );
+        expected.insert("t".into(), Value::Text(vec!["b".into()]));
+        assert_eq!(value, Value::Map(expected));
+    }
+
+    #[test]
+    fn apply_to_root() {
+        let mut value = Value::Map(HashMap::new());
+        let mut map = HashMap::new();
+        map.insert("a".into(), Value::Primitive(Primitive::Uint(1)));
+        let map = Value::Map(map);
+        apply_change(&mut value, &LocalChange::set(Path::root(), map.clone())).unwrap();
+        assert_eq!(value, map);
+
+        // like the frontend, the root can't be anything but a map
+        let list = Value::List(vec![Value::Primitive(Primitive::Uint(1))]);
+        assert!(matches!(
+            apply_change(&mut value, &LocalChange::set(Path::root(), list)),
+            Err(ApplyChangeError::NonMapRoot)
+        ));
+        assert_eq!(value, map);
+
+        assert!(matches!(
+            apply_change(&mut value, &LocalChange::delete(Path::root())),
+            Err(ApplyChangeError::NoSuchPath { path }) if path == Path::root()
+        ));
+    }
+
+    #[test]
+    fn apply_errors_have_paths() {
+        let mut map = HashMap::new();
+        map.insert("c".into(), Value::Primitive(Primitive::Counter(0)));
+        map.insert("s".into(), Value::Primitive(Primitive::Str("s".into())));
+        let mut value = Value::Map(map);
+
+        assert!(matches!(
+            apply_change(&mut value, &LocalChange::set(Path::root().key("c"), Value::Primitive(Primitive::Null))),
+            Err(ApplyChangeError::CannotOverwriteCounter { path }) if path == Path::root().key("c")
+        ));
+        assert!(matches!(
+            apply_change(&mut value, &LocalChange::increment(Path::root().key("s"))),
+            Err(ApplyChangeError::NotACounter { path }) if path == Path::root().key("s")
+        ));
+        assert!(matches!(
+            apply_change(&mut value, &LocalChange::delete(Path::root().key("x").key("y"))),
+            Err(ApplyChangeError::NoSuchPath { path }) if path == Path::root().key("x")
+        ));
+        assert!(matches!(
+            apply_change(
+                &mut value,
+                &LocalChange::insert(Path::root().key("s").index(0), Value::Primitive(Primitive::Null))
+            ),
+            Err(ApplyChangeError::NotASequence { path }) if path == Path::root().key("s")
+        ));
+    }
+}
@@ -1,6 +1,48 @@
-use std::convert::TryInto;
+//! Calculating the changes between automerge values.
 
-use automerge::{InvalidChangeRequest, LocalChange, Path, Primitive, Value};
+use std::{
+    borrow::Cow,
+    collections::{hash_map, hash_map::DefaultHasher, HashMap, VecDeque},
+    convert::TryInto,
+    hash::{Hash, Hasher},
+};
+
+use automerge::{InvalidChangeRequest, LocalChange, Path, PathElement, Primitive, Value};
+use smol_str::SmolStr;
+
+use crate::ListKeys;
+
+mod options;
+mod report;
+pub(crate) mod sequence;
+
+pub use options::{CounterPolicy, DiffOptions, PathFilter, TypeChangePolicy};
+pub use report::{diff_report, ColoredDiffReport, DiffKind, DiffNode, DiffReport};
+
+/// An error type for diffing values.
+#[derive(Debug, thiserror::Error)]
+pub enum DiffError {
+    /// The changes needed can't be expressed in automerge, such as overwriting a counter.
+    #[error(transparent)]
+    InvalidChangeRequest(#[from] InvalidChangeRequest),
+    /// The type of the value changed while using [`TypeChangePolicy::Error`].
+    #[error("the type of the value at {path} changed")]
+    MismatchedTypes {
+        /// The path to the value.
+        path: Path,
+    },
+}
+
+/// An element of a list which has moved to a different position.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Move {
+    /// The index of the element in the old list.
+    pub old_index: usize,
+    /// The index of the element in the new list.
+    pub new_index: usize,
+    /// The path to the list, as it is after the changes have been applied.
+    pub path: Path,
+}
 
 /// Calculate the [`LocalChange`]s between the two values.
 ///
@@ -9,261 +51,801 @@ pub fn diff_values(new: &Value, old: &Value) -> Result<Vec<LocalChange>, Invalid
     diff_with_path(Some(new), Some(old), Path::root())
 }
 
+/// Calculate the [`LocalChange`]s between the two values, matching up the elements of lists by
+/// the given keys.
+///
+/// Elements of a keyed list with the same key are diffed recursively, only those without a match
+/// in the other list are inserted or deleted.
+pub fn diff_values_with_keys(
+    new: &Value,
+    old: &Value,
+    keys: &ListKeys,
+) -> Result<Vec<LocalChange>, InvalidChangeRequest> {
+    let options = DiffOptions {
+        list_keys: keys.clone(),
+        ..DiffOptions::default()
+    };
+    diff_values_with(new, old, &options).map_err(default_options_error)
+}
+
+/// Calculate the [`LocalChange`]s between the two values using the given options.
+pub fn diff_values_with(
+    new: &Value,
+    old: &Value,
+    options: &DiffOptions,
+) -> Result<Vec<LocalChange>, DiffError> {
+    diff_values_with_moves(new, old, options).map(|(changes, _)| changes)
+}
+
+/// Calculate the [`LocalChange`]s between the two values along with the elements of lists which
+/// have moved.
+///
+/// A moved element is one which was removed from a list and inserted elsewhere in it, either
+/// being equal or having the same key. Moved elements are still deleted and reinserted, but they
+/// are never paired up with other elements so the elements around them keep their identity.
+pub fn diff_values_with_moves(
+    new: &Value,
+    old: &Value,
+    options: &DiffOptions,
+) -> Result<(Vec<LocalChange>, Vec<Move>), DiffError> {
+    let mut iter =
+        DiffIter::new(Some(new), Some(old), Path::root(), Cow::Borrowed(options)).with_moves();
+    let changes = iter.by_ref().collect::<Result<_, _>>()?;
+    Ok((changes, iter.moves))
+}
+
 /// Calculate the [`LocalChange`]s between the two values that start from the given path.
 pub fn diff_with_path(
     new: Option<&Value>,
     old: Option<&Value>,
     path: Path,
 ) -> Result<Vec<LocalChange>, InvalidChangeRequest> {
-    match (new, old) {
-        (None, None) => Ok(Vec::new()),
-        (Some(new), None) => Ok(vec![LocalChange::set(path, new.clone())]),
-        (None, Some(_)) => Ok(vec![LocalChange::delete(path)]),
-        (Some(new), Some(old)) => {
-            match (new, old) {
-                (Value::Map(new_map), Value::Map(old_map)) => {
-                    let mut changes = Vec::new();
-                    for (k, v) in new_map {
-                        if let Some(old_v) = old_map.get(k) {
-                            // changed
-                            changes.append(&mut diff_with_path(
-                                Some(v),
-                                Some(old_v),
-                                path.clone().key(k.clone()),
-                            )?)
-                        } else {
-                            // new
-                            changes.push(LocalChange::set(path.clone().key(k.clone()), v.clone()))
+    diff_with_path_and_options(new, old, path, &DiffOptions::default())
+        .map_err(default_options_error)
+}
+
+/// Get the error from diffing with the default options, which replace values whose type has
+/// changed rather than failing.
+fn default_options_error(error: DiffError) -> InvalidChangeRequest {
+    match error {
+        DiffError::InvalidChangeRequest(e) => e,
+        DiffError::MismatchedTypes { .. } => {
+            unreachable!("types are only checked with TypeChangePolicy::Error")
+        }
+    }
+}
+
+/// Calculate the [`LocalChange`]s between the two values that start from the given path using
+/// the given options.
+///
+/// Paths which aren't allowed by the [`PathFilter`] in the options produce no changes.
+pub fn diff_with_path_and_options(
+    new: Option<&Value>,
+    old: Option<&Value>,
+    path: Path,
+    options: &DiffOptions,
+) -> Result<Vec<LocalChange>, DiffError> {
+    diff_iter_with(new, old, path, options).collect()
+}
+
+/// Lazily calculate the [`LocalChange`]s between the two values.
+///
+/// The changes are calculated as the iterator is advanced, in the same order as [`diff_values`]
+/// gives them, so stopping early skips diffing the rest of the values.
+pub fn diff_iter<'a>(new: &'a Value, old: &'a Value) -> DiffIter<'a> {
+    DiffIter::new(
+        Some(new),
+        Some(old),
+        Path::root(),
+        Cow::Owned(DiffOptions::default()),
+    )
+}
+
+/// Lazily calculate the [`LocalChange`]s between the two values that start from the given path
+/// using the given options.
+///
+/// See [`diff_iter`] for details.
+pub fn diff_iter_with<'a>(
+    new: Option<&'a Value>,
+    old: Option<&'a Value>,
+    path: Path,
+    options: &'a DiffOptions,
+) -> DiffIter<'a> {
+    DiffIter::new(new, old, path, Cow::Borrowed(options))
+}
+
+/// Calculate the [`LocalChange`]s from `old` to `new` along with the inverse changes which restore
+/// `old` once applied after them.
+///
+/// Counters are restored by incrementing them by the negated delta so concurrent increments are
+/// kept.
+pub fn invert(new: &Value, old: &Value) -> Result<(Vec<LocalChange>, Vec<LocalChange>), DiffError> {
+    let forward = diff_with_path(Some(new), Some(old), Path::root())?;
+    let inverse = diff_with_path(Some(old), Some(new), Path::root())?;
+    Ok((forward, inverse))
+}
+
+/// An iterator over the changes between two values, created by [`diff_iter`] and
+/// [`diff_iter_with`].
+///
+/// Iteration stops after the first error.
+#[derive(Debug)]
+pub struct DiffIter<'a> {
+    options: Cow<'a, DiffOptions>,
+    detect_moves: bool,
+    moves: Vec<Move>,
+    /// The work left to do, with the next piece at the end.
+    stack: Vec<Frame<'a>>,
+}
+
+/// A piece of work for a [`DiffIter`].
+#[derive(Debug)]
+enum Frame<'a> {
+    /// Diff the two values.
+    Diff {
+        new: Option<&'a Value>,
+        old: Option<&'a Value>,
+        path: Path,
+    },
+    /// Yield the change.
+    Change(LocalChange),
+    /// Diff the remaining entries of two maps.
+    Map(MapEntries<'a>),
+    /// Do the pieces of work in order.
+    Frames(std::vec::IntoIter<Self>),
+    /// Yield the error, found while diffing in parallel.
+    #[cfg(feature = "rayon")]
+    Error(DiffError),
+}
+
+/// The entries of two maps still to be diffed, those in the new map first and then those only in
+/// the old one.
+#[derive(Debug)]
+struct MapEntries<'a> {
+    path: Path,
+    new_map: &'a HashMap<SmolStr, Value>,
+    old_map: &'a HashMap<SmolStr, Value>,
+    new_entries: hash_map::Iter<'a, SmolStr, Value>,
+    old_entries: hash_map::Iter<'a, SmolStr, Value>,
+}
+
+impl<'a> MapEntries<'a> {
+    fn new(
+        path: Path,
+        new_map: &'a HashMap<SmolStr, Value>,
+        old_map: &'a HashMap<SmolStr, Value>,
+    ) -> Self {
+        Self {
+            path,
+            new_map,
+            old_map,
+            new_entries: new_map.iter(),
+            old_entries: old_map.iter(),
+        }
+    }
+}
+
+impl<'a> Iterator for MapEntries<'a> {
+    /// The new and old values of an entry, with its path.
+    type Item = (Option<&'a Value>, Option<&'a Value>, Path);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some((k, v)) = self.new_entries.next() {
+            // changed or new
+            return Some((
+                Some(v),
+                self.old_map.get(k),
+                self.path.clone().key(k.clone()),
+            ));
+        }
+        let new_map = self.new_map;
+        self.old_entries
+            .find(|(k, _)| !new_map.contains_key(*k))
+            // removed
+            .map(|(k, old_v)| (None, Some(old_v), self.path.clone().key(k.clone())))
+    }
+}
+
+impl<'a> DiffIter<'a> {
+    fn new(
+        new: Option<&'a Value>,
+        old: Option<&'a Value>,
+        path: Path,
+        options: Cow<'a, DiffOptions>,
+    ) -> Self {
+        Self {
+            options,
+            detect_moves: false,
+            moves: Vec::new(),
+            stack: vec![Frame::Diff { new, old, path }],
+        }
+    }
+
+    /// Find the elements of lists which have moved, as [`diff_values_with_moves`] does.
+    ///
+    /// Without this, moved elements are diffed like any others.
+    pub fn with_moves(mut self) -> Self {
+        self.detect_moves = true;
+        self
+    }
+
+    /// The elements of lists which have moved, found in the values diffed so far if
+    /// [`DiffIter::with_moves`] was used.
+    pub fn moves(&self) -> &[Move] {
+        &self.moves
+    }
+
+    /// Diff the entries of two maps in parallel, giving their changes in the same order as diffing
+    /// them one at a time would.
+    #[cfg(feature = "rayon")]
+    fn diff_parallel(&mut self, entries: MapEntries<'a>) -> Frame<'a> {
+        use rayon::prelude::*;
+
+        let options = &*self.options;
+        let detect_moves = self.detect_moves;
+        let results = entries
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|(new, old, path)| {
+                let mut iter = DiffIter::new(new, old, path, Cow::Borrowed(options));
+                iter.detect_moves = detect_moves;
+                let changes = iter.by_ref().collect::<Vec<_>>();
+                (changes, iter.moves)
+            })
+            .collect::<Vec<_>>();
+        let mut frames = Vec::new();
+        for (changes, mut moves) in results {
+            self.moves.append(&mut moves);
+            for change in changes {
+                match change {
+                    Ok(change) => frames.push(Frame::Change(change)),
+                    Err(e) => {
+                        // a serial diff would stop here
+                        frames.push(Frame::Error(e));
+                        return Frame::Frames(frames.into_iter());
+                    }
+                }
+            }
+        }
+        Frame::Frames(frames.into_iter())
+    }
+
+    /// Diff the two values, giving the work to do for their changes.
+    fn diff(
+        &mut self,
+        new: Option<&'a Value>,
+        old: Option<&'a Value>,
+        path: Path,
+    ) -> Result<Option<Frame<'a>>, DiffError> {
+        if !self.options.filter.allows(&path) {
+            return Ok(None);
+        }
+        match (new, old) {
+            (None, None) => Ok(None),
+            (Some(new), None) => {
+                let new = self.options.filter.prune(&path, new);
+                Ok(Some(Frame::Change(LocalChange::set(path, new))))
+            }
+            (None, Some(_)) => Ok(Some(Frame::Change(LocalChange::delete(path)))),
+            (Some(new), Some(old)) => {
+                match (new, old) {
+                    (Value::Map(new_map), Value::Map(old_map))
+                    | (Value::Table(new_map), Value::Table(old_map)) => {
+                        let entries = MapEntries::new(path, new_map, old_map);
+                        #[cfg(feature = "rayon")]
+                        if new_map.len().max(old_map.len()) >= self.options.parallel_threshold {
+                            return Ok(Some(self.diff_parallel(entries)));
                         }
+                        Ok(Some(Frame::Map(entries)))
                     }
-                    for k in old_map.keys() {
-                        if !new_map.contains_key(k) {
-                            // removed
-                            changes.push(LocalChange::delete(path.clone().key(k.clone())))
+                    (Value::List(new_vec), Value::List(old_vec)) => {
+                        let options = &*self.options;
+                        let key = options.list_keys.get(&path);
+                        let eq = |o: &Value, n: &Value| {
+                            key.map_or_else(|| o == n, |key| same_key(o, n, key))
+                        };
+                        let ops = sequence::diff(old_vec, new_vec, eq);
+                        let mut moved_old = vec![false; old_vec.len()];
+                        let mut moved_new = vec![false; new_vec.len()];
+                        if self.detect_moves {
+                            for (old_index, new_index) in
+                                find_moves(&ops, old_vec, new_vec, key.map(SmolStr::as_str))
+                            {
+                                moved_old[old_index] = true;
+                                moved_new[new_index] = true;
+                                self.moves.push(Move {
+                                    old_index,
+                                    new_index,
+                                    path: path.clone(),
+                                })
+                            }
+                        }
+                        let mut frames = Vec::new();
+                        // the index in the list as the changes so far have been applied
+                        let mut i = 0;
+                        for op in ops {
+                            match op {
+                                sequence::Op::Equal { old, new } => {
+                                    if key.is_some() {
+                                        // matched by key but may have changed
+                                        for (old_i, new_i) in old.zip(new) {
+                                            frames.push(Frame::Diff {
+                                                new: Some(&new_vec[new_i]),
+                                                old: Some(&old_vec[old_i]),
+                                                path: path.clone().index(i.try_into().unwrap()),
+                                            });
+                                            i += 1;
+                                        }
+                                    } else {
+                                        i += old.len()
+                                    }
+                                }
+                                sequence::Op::Replace { old, new } => {
+                                    let mut kept = 0;
+                                    for old_i in old.clone() {
+                                        if moved_old[old_i] {
+                                            // moved away
+                                            frames.push(Frame::Change(LocalChange::delete(
+                                                path.clone().index((i + kept).try_into().unwrap()),
+                                            )))
+                                        } else {
+                                            kept += 1
+                                        }
+                                    }
+                                    // the rest of the old elements, from the current index
+                                    let mut remaining = old.filter(|old_i| !moved_old[*old_i]);
+                                    for new_i in new {
+                                        let v = &new_vec[new_i];
+                                        // keyed elements have a different identity so are never
+                                        // paired up
+                                        let paired = if key.is_none() && !moved_new[new_i] {
+                                            remaining.next()
+                                        } else {
+                                            None
+                                        };
+                                        match paired {
+                                            Some(old_i)
+                                                if can_diff_in_place(
+                                                    v,
+                                                    &old_vec[old_i],
+                                                    options,
+                                                ) =>
+                                            {
+                                                // changed
+                                                frames.push(Frame::Diff {
+                                                    new: Some(v),
+                                                    old: Some(&old_vec[old_i]),
+                                                    path: path.clone().index(i.try_into().unwrap()),
+                                                })
+                                            }
+                                            Some(_) => {
+                                                // replaced
+                                                frames.push(Frame::Change(LocalChange::delete(
+                                                    path.clone().index(i.try_into().unwrap()),
+                                                )));
+                                                let path =
+                                                    path.clone().index(i.try_into().unwrap());
+                                                let v = options.filter.prune(&path, v);
+                                                frames.push(Frame::Change(LocalChange::insert(
+                                                    path, v,
+                                                )))
+                                            }
+                                            None => {
+                                                // new or moved here
+                                                let path =
+                                                    path.clone().index(i.try_into().unwrap());
+                                                let v = options.filter.prune(&path, v);
+                                                frames.push(Frame::Change(LocalChange::insert(
+                                                    path, v,
+                                                )))
+                                            }
+                                        }
+                                        i += 1;
+                                    }
+                                    for _ in remaining {
+                                        // removed
+                                        frames.push(Frame::Change(LocalChange::delete(
+                                            path.clone().index(i.try_into().unwrap()),
+                                        )))
+                                    }
+                                }
+                            }
                         }
+                        Ok(Some(Frame::Frames(frames.into_iter())))
                     }
-                    Ok(changes)
-                }
-                (Value::Table(new_map), Value::Table(old_map)) => {
-                    let mut changes = Vec::new();
-                    for (k, v) in new_map {
-                        if let Some(old_v) = old_map.get(k) {
-                            // changed
-                            changes.append(&mut diff_with_path(
-                                Some(v),
-                                Some(old_v),
-                                path.clone().key(k.clone()),
-                            )?)
+                    (Value::Text(new_vec), Value::Text(old_vec)) => {
+                        let mut frames = Vec::new();
+                        // only splice the graphemes so that concurrent edits to the rest of the
+                        // text are kept
+                        let mut i = 0;
+                        for op in sequence::diff(old_vec, new_vec, PartialEq::eq) {
+                            match op {
+                                sequence::Op::Equal { old, .. } => i += old.len(),
+                                sequence::Op::Replace { old, new } => {
+                                    for _ in old {
+                                        // removed
+                                        frames.push(Frame::Change(LocalChange::delete(
+                                            path.clone().index(i.try_into().unwrap()),
+                                        )))
+                                    }
+                                    for new_i in new {
+                                        // new
+                                        frames.push(Frame::Change(LocalChange::insert(
+                                            path.clone().index(i.try_into().unwrap()),
+                                            Value::Primitive(Primitive::Str(
+                                                new_vec[new_i].clone(),
+                                            )),
+                                        )));
+                                        i += 1;
+                                    }
+                                }
+                            }
+                        }
+                        Ok(Some(Frame::Frames(frames.into_iter())))
+                    }
+                    (
+                        Value::Primitive(Primitive::Str(new_string)),
+                        Value::Primitive(Primitive::Str(old_string)),
+                    ) => {
+                        // just set this, we can't address the characters so this may be a thing such as an enum
+                        if new_string == old_string {
+                            Ok(None)
                         } else {
-                            // new
-                            changes.push(LocalChange::set(path.clone().key(k.clone()), v.clone()))
+                            Ok(Some(Frame::Change(LocalChange::set(
+                                path,
+                                Value::Primitive(Primitive::Str(new_string.clone())),
+                            ))))
                         }
                     }
-                    for k in old_map.keys() {
-                        if !new_map.contains_key(k) {
-                            // removed
-                            changes.push(LocalChange::delete(path.clone().key(k.clone())))
+                    (
+                        Value::Primitive(Primitive::Bytes(new)),
+                        Value::Primitive(Primitive::Bytes(old)),
+                    ) => {
+                        if new == old {
+                            Ok(None)
+                        } else {
+                            Ok(Some(Frame::Change(LocalChange::set(
+                                path,
+                                Value::Primitive(Primitive::Bytes(new.clone())),
+                            ))))
                         }
                     }
-                    Ok(changes)
-                }
-                (Value::List(new_vec), Value::List(old_vec)) => {
-                    let mut changes = Vec::new();
-                    // naive
-                    for (i, v) in new_vec.iter().enumerate() {
-                        if let Some(old_v) = old_vec.get(i) {
-                            // changed
-                            changes.append(&mut diff_with_path(
-                                Some(v),
-                                Some(old_v),
-                                path.clone().index(i.try_into().unwrap()),
-                            )?)
+                    (
+                        Value::Primitive(Primitive::Int(new_int)),
+                        Value::Primitive(Primitive::Int(old_int)),
+                    ) => {
+                        if new_int == old_int {
+                            Ok(None)
                         } else {
-                            // new
-                            changes.push(LocalChange::insert(
-                                path.clone().index(i.try_into().unwrap()),
-                                v.clone(),
-                            ))
+                            Ok(Some(Frame::Change(LocalChange::set(
+                                path,
+                                Value::Primitive(Primitive::Int(*new_int)),
+                            ))))
                         }
                     }
-                    // reverse so delete from the end
-                    for i in (new_vec.len()..old_vec.len()).rev() {
-                        // removed
-                        changes.push(LocalChange::delete(
-                            path.clone().index(i.try_into().unwrap()),
-                        ))
+                    (
+                        Value::Primitive(Primitive::Uint(new_int)),
+                        Value::Primitive(Primitive::Uint(old_int)),
+                    ) => {
+                        if new_int == old_int {
+                            Ok(None)
+                        } else {
+                            Ok(Some(Frame::Change(LocalChange::set(
+                                path,
+                                Value::Primitive(Primitive::Uint(*new_int)),
+                            ))))
+                        }
                     }
-                    Ok(changes)
-                }
-                (Value::Text(new_vec), Value::Text(old_vec)) => {
-                    let mut changes = Vec::new();
-                    // naive
-                    for (i, v) in new_vec.iter().enumerate() {
-                        if let Some(old_v) = old_vec.get(i) {
-                            if v != old_v {
-                                // changed
-                                changes.push(LocalChange::set(
-                                    path.clone().index(i.try_into().unwrap()),
-                                    Value::Primitive(Primitive::Str(v.clone())),
-                                ))
-                            }
+                    (
+                        Value::Primitive(Primitive::F64(new_int)),
+                        Value::Primitive(Primitive::F64(old_int)),
+                    ) => {
+                        if floats_equal(*new_int, *old_int, self.options.float_epsilon) {
+                            Ok(None)
                         } else {
-                            // new
-                            changes.push(LocalChange::insert(
-                                path.clone().index(i.try_into().unwrap()),
-                                Value::Primitive(Primitive::Str(v.clone())),
-                            ))
+                            Ok(Some(Frame::Change(LocalChange::set(
+                                path,
+                                Value::Primitive(Primitive::F64(*new_int)),
+                            ))))
+                        }
+                    }
+                    (
+                        Value::Primitive(Primitive::Counter(new_int)),
+                        Value::Primitive(Primitive::Counter(old_int)),
+                    ) => {
+                        let policy = if new_int < old_int {
+                            self.options.counter_decrease
+                        } else {
+                            CounterPolicy::Decrement
+                        };
+                        match (new_int.checked_sub(*old_int), policy) {
+                            (Some(0), _) => Ok(None),
+                            (Some(diff), CounterPolicy::Decrement) => {
+                                Ok(Some(Frame::Change(LocalChange::increment_by(path, diff))))
+                            }
+                            (_, CounterPolicy::Reset) => Ok(Some(replace(path, new.clone()))),
+                            (None, _) | (_, CounterPolicy::Error) => {
+                                Err(InvalidChangeRequest::CannotOverwriteCounter { path }.into())
+                            }
                         }
                     }
-                    // reverse so delete from the end
-                    for i in (new_vec.len()..old_vec.len()).rev() {
-                        // removed
-                        changes.push(LocalChange::delete(
-                            path.clone().index(i.try_into().unwrap()),
-                        ))
+                    (
+                        Value::Primitive(Primitive::Timestamp(new_int)),
+                        Value::Primitive(Primitive::Timestamp(old_int)),
+                    ) => {
+                        if new_int == old_int {
+                            Ok(None)
+                        } else {
+                            Ok(Some(Frame::Change(LocalChange::set(
+                                path,
+                                Value::Primitive(Primitive::Timestamp(*new_int)),
+                            ))))
+                        }
                     }
-                    Ok(changes)
-                }
-                (
-                    Value::Primitive(Primitive::Str(new_string)),
-                    Value::Primitive(Primitive::Str(old_string)),
-                ) => {
-                    // just set this, we can't address the characters so this may be a thing such as an enum
-                    if new_string == old_string {
-                        Ok(Vec::new())
-                    } else {
-                        Ok(vec![LocalChange::set(
-                            path,
-                            Value::Primitive(Primitive::Str(new_string.clone())),
-                        )])
+                    (
+                        Value::Primitive(Primitive::Cursor(new_cursor)),
+                        Value::Primitive(Primitive::Cursor(old_cursor)),
+                    ) => {
+                        if new_cursor == old_cursor {
+                            Ok(None)
+                        } else {
+                            Ok(Some(Frame::Change(LocalChange::set(
+                                path,
+                                Value::Primitive(Primitive::Cursor(new_cursor.clone())),
+                            ))))
+                        }
                     }
-                }
-                (
-                    Value::Primitive(Primitive::Bytes(new)),
-                    Value::Primitive(Primitive::Bytes(old)),
-                ) => {
-                    if new == old {
-                        Ok(Vec::new())
-                    } else {
-                        Ok(vec![LocalChange::set(
-                            path,
-                            Value::Primitive(Primitive::Bytes(new.clone())),
-                        )])
+                    (
+                        Value::Primitive(Primitive::Boolean(new_bool)),
+                        Value::Primitive(Primitive::Boolean(old_bool)),
+                    ) => {
+                        if new_bool == old_bool {
+                            Ok(None)
+                        } else {
+                            Ok(Some(Frame::Change(LocalChange::set(
+                                path,
+                                Value::Primitive(Primitive::Boolean(*new_bool)),
+                            ))))
+                        }
                     }
-                }
-                (
-                    Value::Primitive(Primitive::Int(new_int)),
-                    Value::Primitive(Primitive::Int(old_int)),
-                ) => {
-                    if new_int == old_int {
-                        Ok(Vec::new())
-                    } else {
-                        Ok(vec![LocalChange::set(
-                            path,
-                            Value::Primitive(Primitive::Int(*new_int)),
-                        )])
+                    (Value::Primitive(Primitive::Null), Value::Primitive(Primitive::Null)) => {
+                        Ok(None)
                     }
-                }
-                (
-                    Value::Primitive(Primitive::Uint(new_int)),
-                    Value::Primitive(Primitive::Uint(old_int)),
-                ) => {
-                    if new_int == old_int {
-                        Ok(Vec::new())
-                    } else {
-                        Ok(vec![LocalChange::set(
-                            path,
-                            Value::Primitive(Primitive::Uint(*new_int)),
-                        )])
+                    // handle mismatch combinations
+                    (n, Value::Primitive(Primitive::Counter(_))) => {
+                        match self.options.type_change {
+                            TypeChangePolicy::Replace => {
+                                // counters can't be set over so delete them first
+                                let n = self.options.filter.prune(&path, n);
+                                Ok(Some(replace(path, n)))
+                            }
+                            TypeChangePolicy::Error => {
+                                Err(InvalidChangeRequest::CannotOverwriteCounter { path }.into())
+                            }
+                        }
                     }
-                }
-                (
-                    Value::Primitive(Primitive::F64(new_int)),
-                    Value::Primitive(Primitive::F64(old_int)),
-                ) =>
-                {
-                    #[allow(clippy::float_cmp)]
-                    if new_int == old_int {
-                        Ok(Vec::new())
-                    } else {
-                        Ok(vec![LocalChange::set(
-                            path,
-                            Value::Primitive(Primitive::F64(*new_int)),
-                        )])
+                    (Value::Primitive(Primitive::Null), _) => Ok(Some(Frame::Change(
+                        LocalChange::set(path, Value::Primitive(Primitive::Null)),
+                    ))),
+                    (v, Value::Primitive(Primitive::Null)) => {
+                        let v = self.options.filter.prune(&path, v);
+                        Ok(Some(Frame::Change(LocalChange::set(path, v))))
                     }
+                    (n, _) => match self.options.type_change {
+                        TypeChangePolicy::Replace => {
+                            let n = self.options.filter.prune(&path, n);
+                            Ok(Some(Frame::Change(LocalChange::set(path, n))))
+                        }
+                        TypeChangePolicy::Error => Err(DiffError::MismatchedTypes { path }),
+                    },
                 }
-                (
-                    Value::Primitive(Primitive::Counter(new_int)),
-                    Value::Primitive(Primitive::Counter(old_int)),
-                ) => {
-                    if new_int == old_int {
-                        Ok(Vec::new())
-                    } else {
-                        let diff = if let Some(diff) = new_int.checked_sub(*old_int) {
-                            diff
-                        } else {
-                            // TODO: perhaps change this behavior or change error type
-                            return Err(InvalidChangeRequest::CannotOverwriteCounter { path });
-                        };
-                        Ok(vec![LocalChange::increment_by(path, diff)])
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for DiffIter<'a> {
+    type Item = Result<LocalChange, DiffError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.stack.pop()? {
+                Frame::Change(change) => return Some(Ok(change)),
+                Frame::Diff { new, old, path } => match self.diff(new, old, path) {
+                    Ok(frame) => self.stack.extend(frame),
+                    Err(e) => {
+                        self.stack.clear();
+                        return Some(Err(e));
                     }
-                }
-                (
-                    Value::Primitive(Primitive::Timestamp(new_int)),
-                    Value::Primitive(Primitive::Timestamp(old_int)),
-                ) => {
-                    if new_int == old_int {
-                        Ok(Vec::new())
-                    } else {
-                        Ok(vec![LocalChange::set(
-                            path,
-                            Value::Primitive(Primitive::Timestamp(*new_int)),
-                        )])
+                },
+                Frame::Map(mut entries) => {
+                    if let Some((new, old, path)) = entries.next() {
+                        self.stack.push(Frame::Map(entries));
+                        self.stack.push(Frame::Diff { new, old, path })
                     }
                 }
-                (
-                    Value::Primitive(Primitive::Cursor(new_cursor)),
-                    Value::Primitive(Primitive::Cursor(_old_cursor)),
-                ) => {
-                    // naive
-                    Ok(vec![LocalChange::set(
-                        path,
-                        Value::Primitive(Primitive::Cursor(new_cursor.clone())),
-                    )])
+                #[cfg(feature = "rayon")]
+                Frame::Error(e) => {
+                    self.stack.clear();
+                    return Some(Err(e));
                 }
-                (
-                    Value::Primitive(Primitive::Boolean(new_bool)),
-                    Value::Primitive(Primitive::Boolean(old_bool)),
-                ) => {
-                    if new_bool == old_bool {
-                        Ok(Vec::new())
-                    } else {
-                        Ok(vec![LocalChange::set(
-                            path,
-                            Value::Primitive(Primitive::Boolean(*new_bool)),
-                        )])
+                Frame::Frames(mut frames) => {
+                    if let Some(next) = frames.next() {
+                        self.stack.push(Frame::Frames(frames));
+                        self.stack.push(next)
                     }
                 }
-                (Value::Primitive(Primitive::Null), Value::Primitive(Primitive::Null)) => {
-                    Ok(Vec::new())
-                }
-                // handle mismatch combinations
-                (_, Value::Primitive(Primitive::Counter(_))) => {
-                    Err(InvalidChangeRequest::CannotOverwriteCounter { path })
-                }
-                (Value::Primitive(Primitive::Null), _) => Ok(vec![LocalChange::set(
-                    path,
-                    Value::Primitive(Primitive::Null),
-                )]),
-                (v, Value::Primitive(Primitive::Null)) => {
-                    Ok(vec![LocalChange::set(path, v.clone())])
+            }
+        }
+    }
+}
+
+/// Match up the elements removed from a list with those inserted into it, giving the pairs of old
+/// and new indices.
+///
+/// Elements of a keyed list are matched by key, as with [`same_key`], and others by value.
+fn find_moves(
+    ops: &[sequence::Op],
+    old: &[Value],
+    new: &[Value],
+    key: Option<&str>,
+) -> Vec<(usize, usize)> {
+    let eq = |o: &Value, n: &Value| key.map_or_else(|| o == n, |key| same_key(o, n, key));
+    let hash = |v: &Value| {
+        let mut hasher = DefaultHasher::new();
+        hash_value(
+            key.and_then(|key| key_value(v, key)).unwrap_or(v),
+            &mut hasher,
+        );
+        hasher.finish()
+    };
+    let mut removed = Vec::new();
+    // the inserted elements bucketed by hash, in order within each bucket
+    let mut inserted = HashMap::<u64, VecDeque<usize>>::new();
+    for op in ops {
+        if let sequence::Op::Replace {
+            old: old_range,
+            new: new_range,
+        } = op
+        {
+            removed.extend(old_range.clone());
+            for new_i in new_range.clone() {
+                inserted
+                    .entry(hash(&new[new_i]))
+                    .or_default()
+                    .push_back(new_i)
+            }
+        }
+    }
+    let mut moves = Vec::new();
+    for old_i in removed {
+        if let Some(bucket) = inserted.get_mut(&hash(&old[old_i])) {
+            if let Some(pos) = bucket
+                .iter()
+                .position(|new_i| eq(&old[old_i], &new[*new_i]))
+            {
+                moves.extend(bucket.remove(pos).map(|new_i| (old_i, new_i)))
+            }
+        }
+    }
+    moves
+}
+
+/// Hash a value such that equal values have equal hashes, whatever the order of map entries.
+fn hash_value<H: Hasher>(value: &Value, state: &mut H) {
+    std::mem::discriminant(value).hash(state);
+    match value {
+        Value::Map(map) | Value::Table(map) => {
+            // sum the hashes of the entries so that their order doesn't matter
+            let entries = map.iter().fold(0_u64, |sum, (k, v)| {
+                let mut hasher = DefaultHasher::new();
+                k.hash(&mut hasher);
+                hash_value(v, &mut hasher);
+                sum.wrapping_add(hasher.finish())
+            });
+            entries.hash(state)
+        }
+        Value::List(values) => {
+            values.len().hash(state);
+            for v in values {
+                hash_value(v, state)
+            }
+        }
+        Value::Text(graphemes) => graphemes.hash(state),
+        Value::Primitive(primitive) => {
+            std::mem::discriminant(primitive).hash(state);
+            match primitive {
+                Primitive::Str(s) => s.hash(state),
+                Primitive::Bytes(b) => b.hash(state),
+                Primitive::Int(i) | Primitive::Counter(i) | Primitive::Timestamp(i) => {
+                    i.hash(state)
                 }
-                (n, _) => Ok(vec![LocalChange::set(path, n.clone())]),
+                Primitive::Uint(u) => u.hash(state),
+                // adding zero makes -0.0 into 0.0, which it is equal to
+                Primitive::F64(f) => (f + 0.0).to_bits().hash(state),
+                Primitive::Boolean(b) => b.hash(state),
+                // equal cursors only need the same hash, so the discriminant will do
+                Primitive::Cursor(_) | Primitive::Null => {}
             }
         }
     }
 }
 
+/// Whether two elements of a keyed list have the same key.
+///
+/// Elements without the key fall back to being compared by value.
+fn same_key(old: &Value, new: &Value, key: &str) -> bool {
+    match (key_value(old, key), key_value(new, key)) {
+        (Some(old_key), Some(new_key)) => old_key == new_key,
+        (None, None) => old == new,
+        _ => false,
+    }
+}
+
+fn key_value<'a>(value: &'a Value, key: &str) -> Option<&'a Value> {
+    if let Value::Map(map) = value {
+        map.get(key)
+    } else {
+        None
+    }
+}
+
+/// Whether an element in a sequence can be diffed against the old element at the same position,
+/// rather than being deleted and inserted again.
+fn can_diff_in_place(new: &Value, old: &Value, options: &DiffOptions) -> bool {
+    match (new, old) {
+        (Value::Primitive(Primitive::Counter(new)), Value::Primitive(Primitive::Counter(old))) => {
+            // pairing elements up is our choice so don't fail on a decrease
+            new >= old || options.counter_decrease != CounterPolicy::Error
+        }
+        // counters can't be overwritten
+        (_, Value::Primitive(Primitive::Counter(_))) => false,
+        // pairing elements up is our choice so don't fail on it
+        _ if options.type_change == TypeChangePolicy::Error => same_type(new, old),
+        _ => true,
+    }
+}
+
+/// Whether the two values have the same type, with null matching anything.
+fn same_type(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Primitive(Primitive::Null), _)
+        | (_, Value::Primitive(Primitive::Null))
+        | (Value::Map(_), Value::Map(_))
+        | (Value::Table(_), Value::Table(_))
+        | (Value::List(_), Value::List(_))
+        | (Value::Text(_), Value::Text(_)) => true,
+        (Value::Primitive(a), Value::Primitive(b)) => {
+            std::mem::discriminant(a) == std::mem::discriminant(b)
+        }
+        _ => false,
+    }
+}
+
+fn floats_equal(new: f64, old: f64, epsilon: f64) -> bool {
+    #[allow(clippy::float_cmp)]
+    let equal = new == old;
+    equal || (new - old).abs() <= epsilon
+}
+
+/// Replace the value at the path, deleting the old one first so that counters can be overwritten.
+fn replace<'a>(path: Path, value: Value) -> Frame<'a> {
+    let in_list = matches!(path.clone().elements().last(), Some(PathElement::Index(_)));
+    let delete = LocalChange::delete(path.clone());
+    let changes = if in_list {
+        vec![delete, LocalChange::insert(path, value)]
+    } else {
+        vec![delete, LocalChange::set(path, value)]
+    };
+    Frame::Frames(
+        changes
+            .into_iter()
+            .map(Frame::Change)
+            .collect::<Vec<_>>()
+            .into_iter(),
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
@@ -432,6 +1014,547 @@ mod tests {
         "###);
     }
 
+    #[test]
+    fn diff_vecs_insert_at_front() {
+        let old_vec = (0..1000)
+            .map(|i| Value::Primitive(Primitive::Uint(i)))
+            .collect::<Vec<_>>();
+        let mut new_vec = old_vec.clone();
+        new_vec.insert(0, Primitive::Str("first".into()).into());
+
+        assert_debug_snapshot!(diff_values(&Value::List(new_vec), &Value::List(old_vec)), @r###"
+        Ok(
+            [
+                LocalChange {
+                    path: Path(
+                        [
+                            Index(
+                                0,
+                            ),
+                        ],
+                    ),
+                    operation: Insert(
+                        Primitive(
+                            Str(
+                                "first",
+                            ),
+                        ),
+                    ),
+                },
+            ],
+        )
+        "###);
+    }
+
+    #[test]
+    fn diff_vecs_recurses_into_changed_elements() {
+        let mut a = HashMap::new();
+        a.insert("a".into(), Value::Primitive(Primitive::Uint(1)));
+        let mut b = HashMap::new();
+        b.insert("b".into(), Value::Primitive(Primitive::Uint(2)));
+        let old_vec = vec![Value::Map(a.clone()), Value::Map(b.clone())];
+        b.insert("b".into(), Value::Primitive(Primitive::Uint(3)));
+        let new_vec = vec![
+            Primitive::Str("first".into()).into(),
+            Value::Map(a),
+            Value::Map(b),
+        ];
+
+        assert_debug_snapshot!(diff_values(&Value::List(new_vec), &Value::List(old_vec)), @r###"
+        Ok(
+            [
+                LocalChange {
+                    path: Path(
+                        [
+                            Index(
+                                0,
+                            ),
+                        ],
+                    ),
+                    operation: Insert(
+                        Primitive(
+                            Str(
+                                "first",
+                            ),
+                        ),
+                    ),
+                },
+                LocalChange {
+                    path: Path(
+                        [
+                            Index(
+                                2,
+                            ),
+                            Key(
+                                "b",
+                            ),
+                        ],
+                    ),
+                    operation: Set(
+                        Primitive(
+                            Uint(
+                                3,
+                            ),
+                        ),
+                    ),
+                },
+            ],
+        )
+        "###);
+    }
+
+    #[test]
+    fn diff_keyed_vecs_match_elements_by_key() {
+        let element = |id, v| {
+            let mut map = HashMap::new();
+            map.insert("id".into(), Value::Primitive(Primitive::Uint(id)));
+            map.insert("v".into(), Value::Primitive(Primitive::Uint(v)));
+            Value::Map(map)
+        };
+        let old_vec = vec![element(1, 1), element(2, 2)];
+        let new_vec = vec![element(2, 3)];
+        let mut keys = ListKeys::new();
+        keys.insert(Vec::new(), "id");
+
+        assert_debug_snapshot!(diff_values_with_keys(&Value::List(new_vec), &Value::List(old_vec), &keys), @r###"
+        Ok(
+            [
+                LocalChange {
+                    path: Path(
+                        [
+                            Index(
+                                0,
+                            ),
+                        ],
+                    ),
+                    operation: Delete,
+                },
+                LocalChange {
+                    path: Path(
+                        [
+                            Index(
+                                0,
+                            ),
+                            Key(
+                                "v",
+                            ),
+                        ],
+                    ),
+                    operation: Set(
+                        Primitive(
+                            Uint(
+                                3,
+                            ),
+                        ),
+                    ),
+                },
+            ],
+        )
+        "###);
+    }
+
+    #[test]
+    fn diff_vecs_detects_moves() {
+        let old_vec = vec![
+            Value::Primitive(Primitive::Str("a".into())),
+            Value::Primitive(Primitive::Str("b".into())),
+            Value::Primitive(Primitive::Str("c".into())),
+        ];
+        let new_vec = vec![old_vec[2].clone(), old_vec[0].clone(), old_vec[1].clone()];
+
+        let (new, old) = (Value::List(new_vec), Value::List(old_vec));
+        let (changes, moves) = diff_values_with_moves(&new, &old, &DiffOptions::default()).unwrap();
+        assert_debug_snapshot!(changes, @r###"
+        [
+            LocalChange {
+                path: Path(
+                    [
+                        Index(
+                            0,
+                        ),
+                    ],
+                ),
+                operation: Insert(
+                    Primitive(
+                        Str(
+                            "c",
+                        ),
+                    ),
+                ),
+            },
+            LocalChange {
+                path: Path(
+                    [
+                        Index(
+                            3,
+                        ),
+                    ],
+                ),
+                operation: Delete,
+            },
+        ]
+        "###);
+        assert_eq!(
+            moves,
+            vec![Move {
+                old_index: 2,
+                new_index: 0,
+                path: Path::root()
+            }]
+        );
+
+        // moves are only looked for when asked for
+        let mut iter = diff_iter(&new, &old);
+        assert_eq!(iter.by_ref().count(), 2);
+        assert!(iter.moves().is_empty());
+    }
+
+    #[test]
+    fn diff_counter_decrease_policies() {
+        let mut old = HashMap::new();
+        old.insert("c".into(), Value::Primitive(Primitive::Counter(5)));
+        let mut new = HashMap::new();
+        new.insert("c".into(), Value::Primitive(Primitive::Counter(2)));
+        let (new, old) = (Value::Map(new), Value::Map(old));
+
+        let mut options = DiffOptions::default();
+        assert_debug_snapshot!(diff_values_with(&new, &old, &options), @r###"
+        Ok(
+            [
+                LocalChange {
+                    path: Path(
+                        [
+                            Key(
+                                "c",
+                            ),
+                        ],
+                    ),
+                    operation: Increment(
+                        -3,
+                    ),
+                },
+            ],
+        )
+        "###);
+
+        options.counter_decrease = CounterPolicy::Error;
+        assert!(matches!(
+            diff_values_with(&new, &old, &options),
+            Err(DiffError::InvalidChangeRequest(
+                InvalidChangeRequest::CannotOverwriteCounter { .. }
+            ))
+        ));
+        // increases are still fine
+        assert!(diff_values_with(&old, &new, &options).is_ok());
+
+        options.counter_decrease = CounterPolicy::Reset;
+        assert_debug_snapshot!(diff_values_with(&new, &old, &options), @r###"
+        Ok(
+            [
+                LocalChange {
+                    path: Path(
+                        [
+                            Key(
+                                "c",
+                            ),
+                        ],
+                    ),
+                    operation: Delete,
+                },
+                LocalChange {
+                    path: Path(
+                        [
+                            Key(
+                                "c",
+                            ),
+                        ],
+                    ),
+                    operation: Set(
+                        Primitive(
+                            Counter(
+                                2,
+                            ),
+                        ),
+                    ),
+                },
+            ],
+        )
+        "###);
+    }
+
+    #[test]
+    fn diff_floats_with_epsilon() {
+        let new = Value::Primitive(Primitive::F64(1.05));
+        let old = Value::Primitive(Primitive::F64(1.0));
+        let mut options = DiffOptions::default();
+        assert_eq!(diff_values_with(&new, &old, &options).unwrap().len(), 1);
+        options.float_epsilon = 0.1;
+        assert!(diff_values_with(&new, &old, &options).unwrap().is_empty());
+    }
+
+    #[test]
+    fn diff_type_change_policies() {
+        let new = Value::Primitive(Primitive::Str("a".into()));
+        let old = Value::Map(HashMap::new());
+        let mut options = DiffOptions::default();
+        assert_eq!(diff_values_with(&new, &old, &options).unwrap().len(), 1);
+        // null isn't a type change
+        assert_eq!(
+            diff_values_with(&new, &Value::Primitive(Primitive::Null), &options)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        options.type_change = TypeChangePolicy::Error;
+        assert!(matches!(
+            diff_values_with(&new, &old, &options),
+            Err(DiffError::MismatchedTypes { .. })
+        ));
+        assert!(diff_values_with(&new, &Value::Primitive(Primitive::Null), &options).is_ok());
+        // elements of lists are inserted and deleted instead
+        assert_eq!(
+            diff_values_with(&Value::List(vec![new]), &Value::List(vec![old]), &options)
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn diff_type_change_policies_over_counters() {
+        let mut old = HashMap::new();
+        old.insert("c".into(), Value::Primitive(Primitive::Counter(1)));
+        let mut new = HashMap::new();
+        new.insert("c".into(), Value::Primitive(Primitive::Str("a".into())));
+        let (new, old) = (Value::Map(new), Value::Map(old));
+
+        let mut options = DiffOptions::default();
+        assert_eq!(
+            diff_values_with(&new, &old, &options).unwrap(),
+            vec![
+                LocalChange::delete(Path::root().key("c")),
+                LocalChange::set(
+                    Path::root().key("c"),
+                    Value::Primitive(Primitive::Str("a".into()))
+                ),
+            ]
+        );
+
+        options.type_change = TypeChangePolicy::Error;
+        assert!(matches!(
+            diff_values_with(&new, &old, &options),
+            Err(DiffError::InvalidChangeRequest(
+                InvalidChangeRequest::CannotOverwriteCounter { .. }
+            ))
+        ));
+    }
+
+    #[test]
+    fn diff_decreased_counters_in_lists() {
+        let old = Value::List(vec![Value::Primitive(Primitive::Counter(5))]);
+        let new = Value::List(vec![Value::Primitive(Primitive::Counter(2))]);
+        let options = DiffOptions {
+            counter_decrease: CounterPolicy::Error,
+            ..DiffOptions::default()
+        };
+        // the elements aren't paired up so the counter is deleted and inserted again
+        assert_eq!(
+            diff_values_with(&new, &old, &options).unwrap(),
+            vec![
+                LocalChange::delete(Path::root().index(0)),
+                LocalChange::insert(
+                    Path::root().index(0),
+                    Value::Primitive(Primitive::Counter(2))
+                ),
+            ]
+        );
+        // increases are still paired up
+        assert_eq!(
+            diff_values_with(&old, &new, &options).unwrap(),
+            vec![LocalChange::increment_by(Path::root().index(0), 3)]
+        );
+    }
+
+    #[test]
+    fn invert_counter_with_negated_delta() {
+        let mut old = HashMap::new();
+        old.insert("c".into(), Value::Primitive(Primitive::Counter(1)));
+        let mut new = HashMap::new();
+        new.insert("c".into(), Value::Primitive(Primitive::Counter(4)));
+
+        let (forward, inverse) = invert(&Value::Map(new), &Value::Map(old)).unwrap();
+        assert_debug_snapshot!((forward, inverse), @r###"
+        (
+            [
+                LocalChange {
+                    path: Path(
+                        [
+                            Key(
+                                "c",
+                            ),
+                        ],
+                    ),
+                    operation: Increment(
+                        3,
+                    ),
+                },
+            ],
+            [
+                LocalChange {
+                    path: Path(
+                        [
+                            Key(
+                                "c",
+                            ),
+                        ],
+                    ),
+                    operation: Increment(
+                        -3,
+                    ),
+                },
+            ],
+        )
+        "###);
+    }
+
+    #[test]
+    fn diff_with_path_filter() {
+        let mut inner = HashMap::new();
+        inner.insert("x".into(), Value::Primitive(Primitive::Uint(1)));
+        inner.insert("y".into(), Value::Primitive(Primitive::Uint(1)));
+        let mut old = HashMap::new();
+        old.insert("a".into(), Value::Map(inner.clone()));
+        old.insert("b".into(), Value::Primitive(Primitive::Uint(1)));
+        inner.insert("x".into(), Value::Primitive(Primitive::Uint(2)));
+        inner.insert("y".into(), Value::Primitive(Primitive::Uint(2)));
+        let mut new = HashMap::new();
+        new.insert("a".into(), Value::Map(inner));
+        new.insert("c".into(), Value::Primitive(Primitive::Uint(2)));
+        let (new, old) = (Value::Map(new), Value::Map(old));
+
+        let paths = |options: &DiffOptions| {
+            let mut paths =
+                diff_with_path_and_options(Some(&new), Some(&old), Path::root(), options)
+                    .unwrap()
+                    .into_iter()
+                    .map(|change| change.path().to_string())
+                    .collect::<Vec<_>>();
+            paths.sort();
+            paths
+        };
+
+        let mut options = DiffOptions::default();
+        assert_eq!(paths(&options), vec!["/a/x", "/a/y", "/b", "/c"]);
+
+        options.filter.include(Path::root().key("a").key("x"));
+        assert_eq!(paths(&options), vec!["/a/x"]);
+
+        options.filter = PathFilter::new();
+        options.filter.exclude(Path::root().key("a"));
+        options.filter.exclude(Path::root().key("b"));
+        assert_eq!(paths(&options), vec!["/c"]);
+
+        options.filter = PathFilter::new();
+        options
+            .filter
+            .set_predicate(|path| path != &Path::root().key("a").key("y"));
+        assert_eq!(paths(&options), vec!["/a/x", "/b", "/c"]);
+    }
+
+    #[test]
+    fn path_filter_skips_values_inside_new_parents() {
+        let mut cache = HashMap::new();
+        cache.insert("public".into(), Value::Primitive(Primitive::Uint(1)));
+        cache.insert("secret".into(), Value::Primitive(Primitive::Uint(2)));
+        let mut new = HashMap::new();
+        new.insert("cache".into(), Value::Map(cache));
+        new.insert(
+            "list".into(),
+            Value::List(vec![Value::Map(new.clone()), Value::Map(new.clone())]),
+        );
+        let new = Value::Map(new);
+        let old = Value::Map(HashMap::new());
+
+        let mut options = DiffOptions::default();
+        options
+            .filter
+            .exclude(Path::root().key("cache").key("secret"));
+        options
+            .filter
+            .set_predicate(|path| !path.to_string().ends_with("/secret"));
+        let mut changes = diff_values_with(&new, &old, &options).unwrap();
+        changes.sort_by_key(|change| change.path().to_string());
+
+        let mut public = HashMap::new();
+        public.insert("public".into(), Value::Primitive(Primitive::Uint(1)));
+        let mut element = HashMap::new();
+        element.insert("cache".into(), Value::Map(public.clone()));
+        assert_eq!(
+            changes,
+            vec![
+                LocalChange::set(Path::root().key("cache"), Value::Map(public)),
+                LocalChange::set(
+                    Path::root().key("list"),
+                    Value::List(vec![Value::Map(element.clone()), Value::Map(element)])
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_iter_is_lazy() {
+        let mut old = HashMap::new();
+        old.insert("c".into(), Value::Primitive(Primitive::Counter(1)));
+        let mut new = HashMap::new();
+        new.insert("c".into(), Value::Primitive(Primitive::Str("c".into())));
+        for i in 0..100_u64 {
+            old.insert(i.to_string().into(), Value::Primitive(Primitive::Uint(i)));
+            new.insert(
+                i.to_string().into(),
+                Value::Primitive(Primitive::Uint(i + 1)),
+            );
+        }
+        let (new, old) = (Value::Map(new), Value::Map(old));
+
+        let strict = DiffOptions {
+            type_change: TypeChangePolicy::Error,
+            ..DiffOptions::default()
+        };
+        let results =
+            diff_iter_with(Some(&new), Some(&old), Path::root(), &strict).collect::<Vec<_>>();
+        // the counter can't be overwritten and nothing is yielded after the error
+        let (last, rest) = results.split_last().unwrap();
+        assert!(last.is_err());
+        assert!(rest.iter().all(Result::is_ok));
+
+        // count the paths diffed, which the filter is asked about
+        let visited = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut counting = DiffOptions::default();
+        // skip the counter so that the diff doesn't stop early
+        counting.filter.exclude(Path::root().key("c"));
+        let counter = visited.clone();
+        counting.filter.set_predicate(move |_| {
+            counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            true
+        });
+        let changes = diff_iter_with(Some(&new), Some(&old), Path::root(), &counting)
+            .take(3)
+            .count();
+        assert_eq!(changes, 3);
+        // just the root and an entry for each change
+        assert_eq!(visited.load(std::sync::atomic::Ordering::SeqCst), 4);
+
+        let mut options = DiffOptions::default();
+        options.filter.exclude(Path::root().key("c"));
+        assert_eq!(
+            diff_iter_with(Some(&new), Some(&old), Path::root(), &options)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap(),
+            diff_values_with(&new, &old, &options).unwrap()
+        );
+    }
+
     #[test]
     fn diff_text() {
         let mut old_text = Vec::new();
@@ -480,7 +1603,17 @@ mod tests {
                             ),
                         ],
                     ),
-                    operation: Set(
+                    operation: Delete,
+                },
+                LocalChange {
+                    path: Path(
+                        [
+                            Index(
+                                0,
+                            ),
+                        ],
+                    ),
+                    operation: Insert(
                         Primitive(
                             Str(
                                 "b",
@@ -512,6 +1645,133 @@ mod tests {
         "###);
     }
 
+    #[test]
+    fn diff_text_splices() {
+        use unicode_segmentation::UnicodeSegmentation;
+
+        let text = |s: &str| Value::Text(s.graphemes(true).map(|g| g.into()).collect());
+
+        assert_debug_snapshot!(diff_values(&text("yüo, a paragraph"), &text("a paragraph")), @r###"
+        Ok(
+            [
+                LocalChange {
+                    path: Path(
+                        [
+                            Index(
+                                0,
+                            ),
+                        ],
+                    ),
+                    operation: Insert(
+                        Primitive(
+                            Str(
+                                "y",
+                            ),
+                        ),
+                    ),
+                },
+                LocalChange {
+                    path: Path(
+                        [
+                            Index(
+                                1,
+                            ),
+                        ],
+                    ),
+                    operation: Insert(
+                        Primitive(
+                            Str(
+                                "ü",
+                            ),
+                        ),
+                    ),
+                },
+                LocalChange {
+                    path: Path(
+                        [
+                            Index(
+                                2,
+                            ),
+                        ],
+                    ),
+                    operation: Insert(
+                        Primitive(
+                            Str(
+                                "o",
+                            ),
+                        ),
+                    ),
+                },
+                LocalChange {
+                    path: Path(
+                        [
+                            Index(
+                                3,
+                            ),
+                        ],
+                    ),
+                    operation: Insert(
+                        Primitive(
+                            Str(
+                                ",",
+                            ),
+                        ),
+                    ),
+                },
+                LocalChange {
+                    path: Path(
+                        [
+                            Index(
+                                4,
+                            ),
+                        ],
+                    ),
+                    operation: Insert(
+                        Primitive(
+                            Str(
+                                " ",
+                            ),
+                        ),
+                    ),
+                },
+            ],
+        )
+        "###);
+
+        assert_debug_snapshot!(diff_values(&text("a lung paragraph"), &text("a long paragraph")), @r###"
+        Ok(
+            [
+                LocalChange {
+                    path: Path(
+                        [
+                            Index(
+                                3,
+                            ),
+                        ],
+                    ),
+                    operation: Delete,
+                },
+                LocalChange {
+                    path: Path(
+                        [
+                            Index(
+                                3,
+                            ),
+                        ],
+                    ),
+                    operation: Insert(
+                        Primitive(
+                            Str(
+                                "u",
+                            ),
+                        ),
+                    ),
+                },
+            ],
+        )
+        "###);
+    }
+
     #[test]
     fn new_and_empty() {
         let old = Value::Primitive(Primitive::Null);
@@ -0,0 +1,194 @@
+use std::{fmt, sync::Arc};
+
+use automerge::{Path, PathElement, Value};
+
+use crate::ListKeys;
+
+/// How to diff a counter whose value has decreased.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CounterPolicy {
+    /// Increment the counter by the (negative) difference, keeping concurrent increments.
+    Decrement,
+    /// Fail with [`automerge::InvalidChangeRequest::CannotOverwriteCounter`].
+    Error,
+    /// Delete the counter and create it again with the new value, losing concurrent increments.
+    Reset,
+}
+
+impl Default for CounterPolicy {
+    fn default() -> Self {
+        Self::Decrement
+    }
+}
+
+/// How to diff a value whose type has changed, such as a map becoming a string.
+///
+/// Changes to and from null are never treated as a change of type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TypeChangePolicy {
+    /// Set the new value in place of the old one.
+    Replace,
+    /// Fail with [`DiffError::MismatchedTypes`](crate::DiffError::MismatchedTypes).
+    Error,
+}
+
+impl Default for TypeChangePolicy {
+    fn default() -> Self {
+        Self::Replace
+    }
+}
+
+/// Options controlling how values are diffed.
+///
+/// The default options match the behaviour of [`diff_values`](crate::diff_values).
+#[derive(Debug, Clone)]
+pub struct DiffOptions {
+    /// How to handle a counter whose value has decreased.
+    pub counter_decrease: CounterPolicy,
+    /// The largest difference between two floats for them to be considered equal.
+    pub float_epsilon: f64,
+    /// How to handle a value whose type has changed.
+    pub type_change: TypeChangePolicy,
+    /// The keys used to match up the elements of lists.
+    pub list_keys: ListKeys,
+    /// Which paths to diff, others produce no changes.
+    pub filter: PathFilter,
+    /// The number of entries a map or table needs for them to be diffed in parallel.
+    ///
+    /// The changes are the same as those from diffing the entries one at a time, but the entries
+    /// of a parallel map are all diffed up front rather than lazily. This is ignored without the
+    /// `rayon` feature.
+    pub parallel_threshold: usize,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self {
+            counter_decrease: CounterPolicy::default(),
+            float_epsilon: 0.0,
+            type_change: TypeChangePolicy::default(),
+            list_keys: ListKeys::default(),
+            filter: PathFilter::default(),
+            parallel_threshold: 1000,
+        }
+    }
+}
+
+impl DiffOptions {
+    /// Create the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// A filter on the paths which are diffed.
+///
+/// A path is diffed if it is inside or an ancestor of one of the included paths (or there are no
+/// included paths), is not inside any of the excluded paths and matches the predicate. Values which
+/// are set as a whole, such as when they are new or change type, have the values inside them which
+/// aren't diffed removed first. Elements of lists and text are always kept so that the indices of
+/// the others don't change.
+#[derive(Clone, Default)]
+pub struct PathFilter {
+    include: Vec<Vec<PathElement>>,
+    exclude: Vec<Vec<PathElement>>,
+    predicate: Option<Predicate>,
+}
+
+type Predicate = Arc<dyn Fn(&Path) -> bool + Send + Sync>;
+
+impl PathFilter {
+    /// Create a new filter which allows all paths.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the filter allows all paths.
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty() && self.predicate.is_none()
+    }
+
+    /// Only diff the given path and those inside it, along with any other included paths.
+    pub fn include(&mut self, path: Path) {
+        self.include.push(path.elements())
+    }
+
+    /// Don't diff the given path or those inside it.
+    pub fn exclude(&mut self, path: Path) {
+        self.exclude.push(path.elements())
+    }
+
+    /// Only diff paths for which the predicate returns true, skipping the values inside them
+    /// otherwise.
+    pub fn set_predicate<F>(&mut self, predicate: F)
+    where
+        F: Fn(&Path) -> bool + Send + Sync + 'static,
+    {
+        self.predicate = Some(Arc::new(predicate))
+    }
+
+    /// Whether the value at the path should be diffed.
+    pub fn allows(&self, path: &Path) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        let elements = path.clone().elements();
+        let included = self.include.is_empty()
+            || self.include.iter().any(|include| {
+                include
+                    .iter()
+                    .zip(&elements)
+                    .all(|(include, element)| include == element)
+            });
+        let excluded = self
+            .exclude
+            .iter()
+            .any(|exclude| elements.starts_with(exclude));
+        included && !excluded && self.predicate.as_ref().map_or(true, |p| p(path))
+    }
+
+    /// Remove the values inside `value`, which is at `path`, which aren't diffed so that it can be
+    /// set as a whole.
+    pub(crate) fn prune(&self, path: &Path, value: &Value) -> Value {
+        if self.is_empty() {
+            return value.clone();
+        }
+        match value {
+            Value::Map(map) | Value::Table(map) => {
+                let pruned = map
+                    .iter()
+                    .filter_map(|(k, v)| {
+                        let path = path.clone().key(k.as_str());
+                        if self.allows(&path) {
+                            Some((k.clone(), self.prune(&path, v)))
+                        } else {
+                            None
+                        }
+                    })
+                    .collect();
+                if let Value::Map(_) = value {
+                    Value::Map(pruned)
+                } else {
+                    Value::Table(pruned)
+                }
+            }
+            Value::List(list) => Value::List(
+                list.iter()
+                    .enumerate()
+                    .map(|(i, v)| self.prune(&path.clone().index(i as u32), v))
+                    .collect(),
+            ),
+            Value::Text(_) | Value::Primitive(_) => value.clone(),
+        }
+    }
+}
+
+impl fmt::Debug for PathFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PathFilter")
+            .field("include", &self.include)
+            .field("exclude", &self.exclude)
+            .field("predicate", &self.predicate.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
@@ -0,0 +1,381 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    convert::TryInto,
+    fmt,
+};
+
+use automerge::{Path, Primitive, Value};
+use smol_str::SmolStr;
+
+use super::{find_moves, sequence};
+
+/// Calculate a structural report of the differences between the two values.
+///
+/// Unlike [`diff_values`](crate::diff_values) this describes what changed rather than how to
+/// change it, so the paths of list elements are their indices in the old list for removed
+/// elements and in the new list for everything else.
+pub fn diff_report(new: &Value, old: &Value) -> DiffReport {
+    DiffReport {
+        root: report(new, old, Path::root()),
+    }
+}
+
+/// A tree of the differences between two values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffReport {
+    root: Option<DiffNode>,
+}
+
+/// A difference at a single path.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffNode {
+    /// The path to the value.
+    pub path: Path,
+    /// How the value differs.
+    pub kind: DiffKind,
+}
+
+/// The ways a value can differ.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiffKind {
+    /// The value was added.
+    Added(Value),
+    /// The value was removed.
+    Removed(Value),
+    /// The value was replaced.
+    Changed {
+        /// The old value.
+        old: Value,
+        /// The new value.
+        new: Value,
+    },
+    /// The list element moved from another index.
+    Moved {
+        /// The index of the element in the old list.
+        old_index: usize,
+        /// The index of the element in the new list.
+        new_index: usize,
+        /// The element.
+        value: Value,
+    },
+    /// The map, table or list has differences in its children.
+    Nested(Vec<DiffNode>),
+}
+
+impl DiffReport {
+    /// Whether the values were equal.
+    pub const fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// The root of the tree, if there are any differences.
+    pub const fn root(&self) -> Option<&DiffNode> {
+        self.root.as_ref()
+    }
+
+    /// The differences at the leaves of the tree, in depth first order.
+    pub fn entries(&self) -> Vec<&DiffNode> {
+        let mut entries = Vec::new();
+        if let Some(root) = &self.root {
+            root.collect_entries(&mut entries)
+        }
+        entries
+    }
+
+    /// Get the difference at the given path.
+    pub fn get(&self, path: &Path) -> Option<&DiffNode> {
+        self.root.as_ref().and_then(|root| root.get(path))
+    }
+
+    /// Display the report as a unified diff, colored with ANSI escape codes.
+    pub const fn colored(&self) -> ColoredDiffReport<'_> {
+        ColoredDiffReport(self)
+    }
+
+    fn write(&self, f: &mut fmt::Formatter<'_>, colored: bool) -> fmt::Result {
+        let line = |f: &mut fmt::Formatter<'_>, color: &str, marker: char, body: fmt::Arguments| {
+            if colored {
+                writeln!(f, "\u{1b}[{}m{} {}\u{1b}[0m", color, marker, body)
+            } else {
+                writeln!(f, "{} {}", marker, body)
+            }
+        };
+        for entry in self.entries() {
+            let path = &entry.path;
+            match &entry.kind {
+                DiffKind::Added(value) => line(
+                    f,
+                    GREEN,
+                    '+',
+                    format_args!("{}: {}", path, DisplayValue(value)),
+                )?,
+                DiffKind::Removed(value) => line(
+                    f,
+                    RED,
+                    '-',
+                    format_args!("{}: {}", path, DisplayValue(value)),
+                )?,
+                DiffKind::Changed { old, new } => {
+                    line(f, RED, '-', format_args!("{}: {}", path, DisplayValue(old)))?;
+                    line(
+                        f,
+                        GREEN,
+                        '+',
+                        format_args!("{}: {}", path, DisplayValue(new)),
+                    )?
+                }
+                DiffKind::Moved {
+                    old_index, value, ..
+                } => line(
+                    f,
+                    CYAN,
+                    '>',
+                    format_args!(
+                        "{}: {} (moved from {})",
+                        path,
+                        DisplayValue(value),
+                        old_index
+                    ),
+                )?,
+                DiffKind::Nested(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+const RED: &str = "31";
+const GREEN: &str = "32";
+const CYAN: &str = "36";
+
+impl fmt::Display for DiffReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, false)
+    }
+}
+
+/// A [`DiffReport`] displayed with colors.
+#[derive(Debug)]
+pub struct ColoredDiffReport<'a>(&'a DiffReport);
+
+impl fmt::Display for ColoredDiffReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.write(f, true)
+    }
+}
+
+impl DiffNode {
+    fn collect_entries<'a>(&'a self, entries: &mut Vec<&'a Self>) {
+        if let DiffKind::Nested(children) = &self.kind {
+            for child in children {
+                child.collect_entries(entries)
+            }
+        } else {
+            entries.push(self)
+        }
+    }
+
+    fn get(&self, path: &Path) -> Option<&Self> {
+        if &self.path == path {
+            Some(self)
+        } else if let DiffKind::Nested(children) = &self.kind {
+            children.iter().find_map(|child| child.get(path))
+        } else {
+            None
+        }
+    }
+}
+
+fn report(new: &Value, old: &Value, path: Path) -> Option<DiffNode> {
+    if new == old {
+        return None;
+    }
+    let kind = match (new, old) {
+        (Value::Map(new_map), Value::Map(old_map))
+        | (Value::Table(new_map), Value::Table(old_map)) => {
+            DiffKind::Nested(report_maps(new_map, old_map, &path))
+        }
+        (Value::List(new_vec), Value::List(old_vec)) => {
+            DiffKind::Nested(report_lists(new_vec, old_vec, &path))
+        }
+        _ => DiffKind::Changed {
+            old: old.clone(),
+            new: new.clone(),
+        },
+    };
+    Some(DiffNode { path, kind })
+}
+
+fn report_maps(
+    new_map: &HashMap<SmolStr, Value>,
+    old_map: &HashMap<SmolStr, Value>,
+    path: &Path,
+) -> Vec<DiffNode> {
+    // sorted so that reports are stable
+    let keys = new_map
+        .keys()
+        .chain(old_map.keys())
+        .collect::<BTreeSet<_>>();
+    keys.into_iter()
+        .filter_map(|k| {
+            let path = path.clone().key(k.clone());
+            match (new_map.get(k), old_map.get(k)) {
+                (Some(new), Some(old)) => report(new, old, path),
+                (Some(new), None) => Some(DiffNode {
+                    path,
+                    kind: DiffKind::Added(new.clone()),
+                }),
+                (None, Some(old)) => Some(DiffNode {
+                    path,
+                    kind: DiffKind::Removed(old.clone()),
+                }),
+                (None, None) => None,
+            }
+        })
+        .collect()
+}
+
+fn report_lists(new_vec: &[Value], old_vec: &[Value], path: &Path) -> Vec<DiffNode> {
+    let index = |i: usize| path.clone().index(i.try_into().unwrap());
+    let ops = sequence::diff(old_vec, new_vec, PartialEq::eq);
+    let mut moved_from = vec![None; new_vec.len()];
+    let mut moved_old = vec![false; old_vec.len()];
+    for (old_index, new_index) in find_moves(&ops, old_vec, new_vec, None) {
+        moved_from[new_index] = Some(old_index);
+        moved_old[old_index] = true;
+    }
+    let mut nodes = Vec::new();
+    for op in ops {
+        if let sequence::Op::Replace { old, new } = op {
+            let mut remaining = old.filter(|old_i| !moved_old[*old_i]);
+            for new_i in new {
+                if let Some(old_index) = moved_from[new_i] {
+                    nodes.push(DiffNode {
+                        path: index(new_i),
+                        kind: DiffKind::Moved {
+                            old_index,
+                            new_index: new_i,
+                            value: new_vec[new_i].clone(),
+                        },
+                    })
+                } else if let Some(old_i) = remaining.next() {
+                    nodes.extend(report(&new_vec[new_i], &old_vec[old_i], index(new_i)))
+                } else {
+                    nodes.push(DiffNode {
+                        path: index(new_i),
+                        kind: DiffKind::Added(new_vec[new_i].clone()),
+                    })
+                }
+            }
+            for old_i in remaining {
+                nodes.push(DiffNode {
+                    path: index(old_i),
+                    kind: DiffKind::Removed(old_vec[old_i].clone()),
+                })
+            }
+        }
+    }
+    nodes
+}
+
+/// Displays a value in a JSON like form.
+struct DisplayValue<'a>(&'a Value);
+
+impl fmt::Display for DisplayValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Value::Map(map) | Value::Table(map) => {
+                let mut keys = map.keys().collect::<Vec<_>>();
+                keys.sort();
+                write!(f, "{{")?;
+                for (i, k) in keys.into_iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{:?}: {}", k.as_str(), DisplayValue(&map[k]))?;
+                }
+                write!(f, "}}")
+            }
+            Value::List(vec) => {
+                write!(f, "[")?;
+                for (i, v) in vec.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", DisplayValue(v))?;
+                }
+                write!(f, "]")
+            }
+            Value::Text(graphemes) => write!(f, "{:?}", graphemes.concat()),
+            Value::Primitive(Primitive::Str(s)) => write!(f, "{:?}", s.as_str()),
+            Value::Primitive(Primitive::Int(i)) => write!(f, "{}", i),
+            Value::Primitive(Primitive::Uint(u)) => write!(f, "{}", u),
+            Value::Primitive(Primitive::F64(n)) => write!(f, "{}", n),
+            Value::Primitive(Primitive::Counter(c)) => write!(f, "counter({})", c),
+            Value::Primitive(Primitive::Timestamp(t)) => write!(f, "timestamp({})", t),
+            Value::Primitive(Primitive::Boolean(b)) => write!(f, "{}", b),
+            Value::Primitive(Primitive::Null) => write!(f, "null"),
+            Value::Primitive(p) => write!(f, "{:?}", p),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use insta::assert_display_snapshot;
+
+    use super::*;
+
+    #[test]
+    fn report_changes() {
+        let mut old = HashMap::new();
+        old.insert("a".into(), Value::Primitive(Primitive::Uint(1)));
+        old.insert("b".into(), Value::Primitive(Primitive::Str("x".into())));
+        old.insert(
+            "l".into(),
+            Value::List(vec![
+                Value::Primitive(Primitive::Uint(1)),
+                Value::Primitive(Primitive::Uint(2)),
+                Value::Primitive(Primitive::Uint(3)),
+            ]),
+        );
+        let mut new = old.clone();
+        new.insert("a".into(), Value::Primitive(Primitive::Uint(2)));
+        new.remove("b");
+        new.insert("c".into(), Value::Primitive(Primitive::Boolean(true)));
+        new.insert(
+            "l".into(),
+            Value::List(vec![
+                Value::Primitive(Primitive::Uint(3)),
+                Value::Primitive(Primitive::Uint(1)),
+                Value::Primitive(Primitive::Uint(2)),
+            ]),
+        );
+
+        let report = diff_report(&Value::Map(new), &Value::Map(old));
+        assert_eq!(report.entries().len(), 4);
+        assert_eq!(
+            report.get(&Path::root().key("c")).map(|node| &node.kind),
+            Some(&DiffKind::Added(Value::Primitive(Primitive::Boolean(true))))
+        );
+        assert_display_snapshot!(report, @r###"
+        - /a: 1
+        + /a: 2
+        - /b: "x"
+        + /c: true
+        > /l/0: 3 (moved from 2)
+        "###);
+        assert_eq!(
+            report.colored().to_string().lines().next(),
+            Some("\u{1b}[31m- /a: 1\u{1b}[0m")
+        );
+    }
+
+    #[test]
+    fn report_equal() {
+        let value = Value::List(vec![Value::Primitive(Primitive::Null)]);
+        let report = diff_report(&value, &value);
+        assert!(report.is_empty());
+        assert_eq!(report.to_string(), "");
+    }
+}
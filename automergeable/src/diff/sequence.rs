@@ -0,0 +1,151 @@
+//! Minimal edit scripts between two sequences.
+//!
+//! This uses the Myers diff from the `similar` crate, grouping its operations into runs of equal
+//! and replaced elements.
+
+use std::ops::Range;
+
+use similar::{algorithms::Capture, DiffTag};
+
+/// An operation transforming a range of the old sequence into a range of the new one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    /// The elements in `old` are equal to those in `new`.
+    Equal {
+        old: Range<usize>,
+        new: Range<usize>,
+    },
+    /// The elements in `old` are replaced by those in `new`, either of which may be empty.
+    Replace {
+        old: Range<usize>,
+        new: Range<usize>,
+    },
+}
+
+/// Calculate the operations needed to turn `old` into `new`, using `eq` to compare elements.
+///
+/// The returned operations cover both sequences in order and are the result of a shortest edit
+/// script, keeping as many elements equal as possible.
+pub fn diff<T, F>(old: &[T], new: &[T], eq: F) -> Vec<Op>
+where
+    F: Fn(&T, &T) -> bool,
+{
+    let old = old
+        .iter()
+        .map(|value| Old { value, eq: &eq })
+        .collect::<Vec<_>>();
+    let new = new.iter().map(|value| New { value }).collect::<Vec<_>>();
+    let mut capture = Capture::new();
+    // capturing the operations can't fail
+    similar::algorithms::myers::diff(&mut capture, &old, 0..old.len(), &new, 0..new.len())
+        .unwrap_or_else(|e| match e {});
+    group(capture.into_ops())
+}
+
+/// Merge runs of deletions and insertions into single replacements.
+fn group(diff_ops: Vec<similar::DiffOp>) -> Vec<Op> {
+    let mut ops = Vec::new();
+    for diff_op in diff_ops {
+        let (tag, old, new) = diff_op.as_tag_tuple();
+        match (tag, ops.last_mut()) {
+            (DiffTag::Equal, _) => ops.push(Op::Equal { old, new }),
+            (
+                _,
+                Some(Op::Replace {
+                    old: last_old,
+                    new: last_new,
+                }),
+            ) => {
+                last_old.end = old.end;
+                last_new.end = new.end;
+            }
+            (_, _) => ops.push(Op::Replace { old, new }),
+        }
+    }
+    ops
+}
+
+/// An element of the old sequence.
+struct Old<'a, T, F> {
+    value: &'a T,
+    eq: &'a F,
+}
+
+/// An element of the new sequence, compared to those of the old one with its equality function.
+struct New<'a, T> {
+    value: &'a T,
+}
+
+impl<'a, T, F> PartialEq<Old<'a, T, F>> for New<'a, T>
+where
+    F: Fn(&T, &T) -> bool,
+{
+    fn eq(&self, old: &Old<'a, T, F>) -> bool {
+        (old.eq)(old.value, self.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edit_distance(ops: &[Op]) -> usize {
+        ops.iter()
+            .map(|op| match op {
+                Op::Equal { .. } => 0,
+                Op::Replace { old, new } => old.len() + new.len(),
+            })
+            .sum()
+    }
+
+    #[test]
+    fn insert_at_front() {
+        let old = vec![1, 2, 3];
+        let new = vec![0, 1, 2, 3];
+        assert_eq!(
+            diff(&old, &new, PartialEq::eq),
+            vec![
+                Op::Replace {
+                    old: 0..0,
+                    new: 0..1
+                },
+                Op::Equal {
+                    old: 0..3,
+                    new: 1..4
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn replace_middle() {
+        let old = "abcdef".chars().collect::<Vec<_>>();
+        let new = "abxyef".chars().collect::<Vec<_>>();
+        assert_eq!(
+            diff(&old, &new, PartialEq::eq),
+            vec![
+                Op::Equal {
+                    old: 0..2,
+                    new: 0..2
+                },
+                Op::Replace {
+                    old: 2..4,
+                    new: 2..4
+                },
+                Op::Equal {
+                    old: 4..6,
+                    new: 4..6
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn shortest_edit_script() {
+        // the classic example from the Myers paper has an edit distance of 5
+        let old = "abcabba".chars().collect::<Vec<_>>();
+        let new = "cbabac".chars().collect::<Vec<_>>();
+        let ops = diff(&old, &new, PartialEq::eq);
+        assert_eq!(edit_distance(&ops), 5);
+    }
+}
@@ -1,10 +1,16 @@
-use std::{error::Error, fmt::Debug};
+use std::{collections::HashMap, error::Error, fmt::Debug};
 
-use automerge::{Path, Value};
+use automerge::{LocalChange, Path, Value};
 use automerge_frontend::MutableDocument;
-use automerge_protocol::Patch;
+use automerge_protocol::{ActorId, OpId, Patch};
 
-use crate::Automergeable;
+use crate::{
+    diff::{DiffKind, DiffReport},
+    migrate::{migrated, outdated},
+    subscribe::{SubscriptionId, Subscriptions},
+    undo::UndoManager,
+    Automergeable, Diff, DiffOptions, FromAutomerge, ListKeys, ToAutomerge,
+};
 
 /// An error type for change operations on documents.
 #[derive(Debug, thiserror::Error)]
@@ -18,6 +24,18 @@ pub enum DocumentChangeError<E: Error = std::convert::Infallible> {
     /// A failure to convert the value in automerge to a typed value.
     #[error(transparent)]
     FromError(#[from] crate::FromAutomergeError),
+    /// A failure to diff the changed value against the old one, such as a change of type with
+    /// [`TypeChangePolicy::Error`](crate::TypeChangePolicy::Error).
+    ///
+    /// Changes which automerge can't make are [`DocumentChangeError::InvalidChangeRequest`]s.
+    #[error(transparent)]
+    DiffError(crate::DiffError),
+    /// A failure to apply the filtered changes to the old value.
+    #[error(transparent)]
+    ApplyChangeError(#[from] crate::ApplyChangeError),
+    /// A failure to migrate the changed value to the current schema version.
+    #[error(transparent)]
+    MigrationError(#[from] crate::MigrationError),
     /// A custom error from the users closure.
     #[error(
         "change error:
@@ -26,12 +44,95 @@ pub enum DocumentChangeError<E: Error = std::convert::Infallible> {
     ChangeError(E),
 }
 
+impl<E: Error> From<crate::DiffError> for DocumentChangeError<E> {
+    fn from(error: crate::DiffError) -> Self {
+        match error {
+            crate::DiffError::InvalidChangeRequest(e) => Self::InvalidChangeRequest(e),
+            e => Self::DiffError(e),
+        }
+    }
+}
+
+/// An error type for applying JSON Patches to documents.
+#[cfg(feature = "json")]
+#[derive(Debug, thiserror::Error)]
+pub enum ApplyJsonPatchError {
+    /// A failure to convert the JSON Patch into changes.
+    #[error(transparent)]
+    JsonPatchError(#[from] crate::JsonPatchError),
+    /// A failure to make the changes to the document.
+    #[error(transparent)]
+    ChangeError(#[from] DocumentChangeError),
+}
+
+/// An error type for creating documents.
+#[derive(Debug, thiserror::Error)]
+pub enum NewDocumentError<E: Error> {
+    /// A failure to get the root value from the frontend.
+    #[error("frontend error: {0}")]
+    FrontendError(E),
+    /// The frontend had no root value.
+    #[error("the frontend has no root value")]
+    NoRootValue,
+    /// A failure to convert the root value to a typed value.
+    #[error(transparent)]
+    FromError(#[from] crate::FromAutomergeError),
+    /// A failure to migrate the root value to the current schema version.
+    #[error(transparent)]
+    MigrationError(#[from] crate::MigrationError),
+    /// A failure to change the frontend to the initial value or write the migration.
+    #[error(transparent)]
+    ChangeError(#[from] DocumentChangeError),
+}
+
+/// An error type for loading documents from saved data.
+#[derive(Debug, thiserror::Error)]
+pub enum LoadDocumentError {
+    /// A failure to load the backend or apply the changes to it.
+    #[error(transparent)]
+    BackendError(#[from] automerge::AutomergeError),
+    /// A failure to apply the backend's patch to the frontend.
+    #[error("invalid patch: {0}")]
+    InvalidPatch(#[from] automerge::InvalidPatch),
+    /// A failure to create the document from the loaded frontend.
+    #[error(transparent)]
+    NewDocumentError(#[from] NewDocumentError<automerge::InvalidPatch>),
+    /// A failure to apply the patch of the migration to the frontend.
+    #[error(transparent)]
+    ApplyPatchError(#[from] ApplyPatchError<automerge::InvalidPatch>),
+}
+
+/// An error type for inspecting the conflicts in documents.
+#[derive(Debug, thiserror::Error)]
+pub enum ConflictsError<E: Error> {
+    /// A failure to get the conflicts from the frontend.
+    #[error("frontend error: {0}")]
+    FrontendError(E),
+}
+
+/// One of the values written concurrently to a path, found with [`Document::conflicts`].
+#[derive(Debug)]
+pub struct ConflictingValue<U> {
+    /// The id of the operation which wrote the value.
+    pub op_id: OpId,
+    /// The actor which wrote the value.
+    pub actor_id: ActorId,
+    /// The value, or the failure to convert it if another actor wrote a different type.
+    pub value: Result<U, crate::FromAutomergeError>,
+}
+
+/// An error type for applying patches to documents.
 #[derive(Debug, thiserror::Error)]
 pub enum ApplyPatchError<E: Error> {
+    /// A failure to apply the patch to the frontend.
     #[error("frontend error: {0}")]
     FrontendError(E),
+    /// A failure to convert the patched value to a typed value.
     #[error(transparent)]
     FromError(#[from] crate::FromAutomergeError),
+    /// A failure to migrate the patched value to the current schema version.
+    #[error(transparent)]
+    MigrationError(#[from] crate::MigrationError),
 }
 
 pub trait Frontend {
@@ -39,13 +140,15 @@ pub trait Frontend {
 
     fn get_value(&self, path: &Path) -> Result<Option<Value>, Self::Error>;
 
-    fn change<C, E>(
+    fn get_conflicts(&self, path: &Path) -> Result<Option<HashMap<OpId, Value>>, Self::Error>;
+
+    fn change<C, O, E>(
         &mut self,
         message: Option<String>,
         closure: C,
-    ) -> Result<Option<automerge_protocol::Change>, E>
+    ) -> Result<(O, Option<automerge_protocol::Change>), E>
     where
-        C: FnOnce(&mut dyn MutableDocument) -> Result<(), E>,
+        C: FnOnce(&mut dyn MutableDocument) -> Result<O, E>,
         E: Error;
 
     fn apply_patch(&mut self, patch: Patch) -> Result<(), Self::Error>;
@@ -58,17 +161,20 @@ impl Frontend for automerge::Frontend {
         Ok(self.get_value(path))
     }
 
-    fn change<C, E>(
+    fn get_conflicts(&self, path: &Path) -> Result<Option<HashMap<OpId, Value>>, Self::Error> {
+        Ok(self.get_conflicts(path))
+    }
+
+    fn change<C, O, E>(
         &mut self,
         message: Option<String>,
         closure: C,
-    ) -> Result<Option<automerge_protocol::Change>, E>
+    ) -> Result<(O, Option<automerge_protocol::Change>), E>
     where
-        C: FnOnce(&mut dyn MutableDocument) -> Result<(), E>,
+        C: FnOnce(&mut dyn MutableDocument) -> Result<O, E>,
         E: Error,
     {
-        let ((), change) = self.change(message, closure)?;
-        Ok(change)
+        self.change(message, closure)
     }
 
     fn apply_patch(&mut self, patch: Patch) -> Result<(), Self::Error> {
@@ -76,6 +182,108 @@ impl Frontend for automerge::Frontend {
     }
 }
 
+/// A change to the value at a path, found when applying a patch to a document.
+///
+/// The paths of list elements are their indices in the old list for removed elements and in the
+/// new list for everything else.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypedChangeEvent {
+    /// A value was inserted.
+    Inserted {
+        /// The path to the value.
+        path: Path,
+        /// The new value.
+        value: Value,
+    },
+    /// A value was removed.
+    Removed {
+        /// The path to the value.
+        path: Path,
+        /// The old value.
+        value: Value,
+    },
+    /// A value was replaced.
+    Updated {
+        /// The path to the value.
+        path: Path,
+        /// The old value.
+        old: Value,
+        /// The new value.
+        new: Value,
+    },
+}
+
+impl TypedChangeEvent {
+    /// The path to the changed value.
+    pub const fn path(&self) -> &Path {
+        match self {
+            Self::Inserted { path, .. } | Self::Removed { path, .. } | Self::Updated { path, .. } => {
+                path
+            }
+        }
+    }
+
+    /// The events for the leaves of a diff report, with moves split into a removal and an
+    /// insertion.
+    fn from_report(report: &DiffReport) -> Vec<Self> {
+        let mut events = Vec::new();
+        for entry in report.entries() {
+            let path = entry.path.clone();
+            match &entry.kind {
+                DiffKind::Added(value) => events.push(Self::Inserted {
+                    path,
+                    value: value.clone(),
+                }),
+                DiffKind::Removed(value) => events.push(Self::Removed {
+                    path,
+                    value: value.clone(),
+                }),
+                DiffKind::Changed { old, new } => events.push(Self::Updated {
+                    path,
+                    old: old.clone(),
+                    new: new.clone(),
+                }),
+                DiffKind::Moved {
+                    old_index, value, ..
+                } => {
+                    events.push(Self::Removed {
+                        path: path.parent().index(*old_index as u32),
+                        value: value.clone(),
+                    });
+                    events.push(Self::Inserted {
+                        path,
+                        value: value.clone(),
+                    });
+                }
+                DiffKind::Nested(_) => {}
+            }
+        }
+        events
+    }
+}
+
+/// Access to the document as it was before a change, for creating cursors into its text.
+pub struct Cursors<'a> {
+    doc: &'a dyn MutableDocument,
+}
+
+impl<'a> Cursors<'a> {
+    /// Create a cursor to the character at `path` in some text, such as
+    /// `Path::root().key("text").index(3)`.
+    ///
+    /// The text must already be in the document, so text added by the current change can't be
+    /// pointed at until a later change. Returns `None` if the path doesn't point into any text.
+    pub fn cursor(&self, path: &Path) -> Option<crate::Cursor> {
+        self.doc.cursor_to_path(path).map(crate::Cursor::from)
+    }
+}
+
+impl<'a> Debug for Cursors<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cursors").finish()
+    }
+}
+
 /// A typed automerge document, wrapping a typical frontend.
 ///
 /// This provides similar functionality to an automerge frontend (including [`Deref`] to one) but with
@@ -92,6 +300,11 @@ where
     frontend: F,
     value: T,
     original: Value,
+    list_keys: ListKeys,
+    subscriptions: Subscriptions,
+    undo: Option<UndoManager>,
+    // whether the stored value is missing version markers, so that the next change writes it whole
+    outdated: bool,
 }
 
 impl<T, F> Document<T, F>
@@ -100,17 +313,89 @@ where
     F: Frontend,
 {
     /// Construct a new document.
+    ///
+    /// Values stored with an older schema version are migrated, but the migration is only written
+    /// to the frontend by the next change, use [`Document::try_new`] to write it straight away.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value in the frontend can't be loaded, see [`Document::try_new`].
     pub fn new(frontend: F) -> Self {
-        let original = frontend
-            .get_value(&Path::root())
-            .expect("Failed to get root value")
-            .expect("No root value");
-        let value = T::from_automerge(&original).expect("Failed to load value");
-        Self {
+        Self::load_frontend(frontend).expect("Failed to load value")
+    }
+
+    /// Construct a new document, loading the value from the frontend.
+    ///
+    /// If the value, or any value nested in it, was stored with an older schema version it is
+    /// migrated, see [`migrate`](crate::migrate). The migration is written to the frontend and the
+    /// change is returned so that it can be applied to a backend.
+    pub fn try_new(
+        frontend: F,
+    ) -> Result<(Self, Option<automerge_protocol::Change>), NewDocumentError<F::Error>> {
+        let mut doc = Self::load_frontend(frontend)?;
+        // empty documents are new so there is nothing to migrate, their markers are written by the
+        // first change
+        let empty = matches!(&doc.original, Value::Map(map) if map.is_empty());
+        let change = if doc.outdated && !empty {
+            let ((), change) = doc.change_inner::<_, _, std::convert::Infallible>(
+                None,
+                DiffOptions::default(),
+                |_, _| Ok(()),
+            )?;
+            change
+        } else {
+            None
+        };
+        Ok((doc, change))
+    }
+
+    /// Load the value from the frontend without writing any migration.
+    fn load_frontend(frontend: F) -> Result<Self, NewDocumentError<F::Error>> {
+        let original = Self::root_value(&frontend)?;
+        let value = T::from_automerge(&*migrated::<T>(&original)?)?;
+        let outdated = outdated(&value.to_automerge(), &original);
+        Ok(Self {
             frontend,
             value,
             original,
-        }
+            list_keys: T::list_keys(),
+            subscriptions: Subscriptions::default(),
+            undo: None,
+            outdated,
+        })
+    }
+
+    /// Construct a new document with an initial value, changing the frontend to match it.
+    ///
+    /// The change which sets up the initial value is returned so that it can be applied to a
+    /// backend.
+    pub fn with_initial(
+        frontend: F,
+        value: T,
+    ) -> Result<(Self, Option<automerge_protocol::Change>), NewDocumentError<F::Error>> {
+        let original = Self::root_value(&frontend)?;
+        let mut doc = Self {
+            frontend,
+            value,
+            original,
+            list_keys: T::list_keys(),
+            subscriptions: Subscriptions::default(),
+            undo: None,
+            outdated: true,
+        };
+        let ((), change) = doc.change_inner::<_, _, std::convert::Infallible>(
+            None,
+            DiffOptions::default(),
+            |_, _| Ok(()),
+        )?;
+        Ok((doc, change))
+    }
+
+    fn root_value(frontend: &F) -> Result<Value, NewDocumentError<F::Error>> {
+        frontend
+            .get_value(&Path::root())
+            .map_err(NewDocumentError::FrontendError)?
+            .ok_or(NewDocumentError::NoRootValue)
     }
 
     /// Retrieve the root value from the frontend and convert it.
@@ -128,29 +413,75 @@ where
     fn change_inner<C, O, E>(
         &mut self,
         message: Option<String>,
+        mut options: DiffOptions,
         change: C,
     ) -> Result<(O, Option<automerge_protocol::Change>), DocumentChangeError<E>>
     where
         E: Error,
-        C: FnOnce(&mut T) -> Result<O, E>,
+        C: FnOnce(&mut T, &Cursors) -> Result<O, E>,
     {
         let mut new_t = self.value.clone();
-        let res = change(&mut new_t).map_err(DocumentChangeError::ChangeError)?;
-        let new_original = new_t.to_automerge();
-        let changes = crate::diff_values(&new_original, &self.original)?;
-        let change =
+        options.list_keys.merge(self.list_keys.clone());
+        let filtered = !options.filter.is_empty();
+        // the written changes are needed to keep filtered values and to record them for undo
+        let keep_applied = filtered || self.undo.is_some();
+        let original = &self.original;
+        let mut applied = Vec::new();
+        // run the closure inside the frontend change so that it can create cursors, then feed the
+        // changes straight to the frontend, stopping at the first error
+        let ((res, new_original), change) =
             self.frontend
-                .change::<_, automerge::InvalidChangeRequest>(message, |doc| {
-                    for change in changes {
+                .change::<_, _, DocumentChangeError<E>>(message, |doc| {
+                    let res = change(&mut new_t, &Cursors { doc: &*doc })
+                        .map_err(DocumentChangeError::ChangeError)?;
+                    let new_original = new_t.to_automerge();
+                    let diff = crate::diff_iter_with(
+                        Some(&new_original),
+                        Some(original),
+                        Path::root(),
+                        &options,
+                    );
+                    for change in diff {
+                        let change = change?;
+                        if keep_applied {
+                            applied.push(change.clone())
+                        }
                         doc.add_change(change)?
                     }
-                    Ok(())
+                    Ok((res, new_original))
                 })?;
+        let new_original = if filtered {
+            // filtered out values weren't changed in the frontend so keep them as they were
+            let mut original = self.original.clone();
+            crate::apply_changes(&mut original, &applied)?;
+            original
+        } else {
+            new_original
+        };
         self.value = new_t;
-        self.original = new_original;
+        // filtered out values may include the version markers
+        self.outdated &= filtered;
+        let old = self.set_original(new_original);
+        self.record_undo(&old, applied, &options);
         Ok((res, change))
     }
 
+    fn change_frontend(
+        &mut self,
+        message: Option<String>,
+        changes: Vec<LocalChange>,
+    ) -> Result<Option<automerge_protocol::Change>, automerge::InvalidChangeRequest> {
+        let ((), change) = self
+            .frontend
+            .change::<_, _, automerge::InvalidChangeRequest>(message, |doc| {
+                for change in changes {
+                    doc.add_change(change)?
+                }
+                Ok(())
+            })?;
+        Ok(change)
+    }
+
     /// Perform a change on the frontend.
     pub fn change<C, O, E>(
         &mut self,
@@ -160,7 +491,7 @@ where
         E: Error,
         C: FnOnce(&mut T) -> Result<O, E>,
     {
-        self.change_inner(None, change)
+        self.change_inner(None, DiffOptions::default(), |t, _| change(t))
     }
 
     /// Perform a change on the frontend with a message.
@@ -173,29 +504,435 @@ where
         E: Error,
         C: FnOnce(&mut T) -> Result<O, E>,
     {
-        self.change_inner(Some(message), change)
+        self.change_inner(Some(message), DiffOptions::default(), |t, _| change(t))
+    }
+
+    /// Perform a change on the frontend, with [`Cursors`] for pointing into the document's text.
+    ///
+    /// Cursors are stored in fields of type [`Cursor`](crate::Cursor) and keep pointing at the
+    /// same character as the text is changed, including by other actors.
+    pub fn change_with_cursors<C, O, E>(
+        &mut self,
+        change: C,
+    ) -> Result<(O, Option<automerge_protocol::Change>), DocumentChangeError<E>>
+    where
+        E: Error,
+        C: FnOnce(&mut T, &Cursors) -> Result<O, E>,
+    {
+        self.change_inner(None, DiffOptions::default(), change)
+    }
+
+    /// Perform a change on the frontend, diffing with the given options.
+    ///
+    /// The list keys of the document's type are used in addition to those in the options. Values
+    /// skipped by the options' [`PathFilter`](crate::PathFilter) are kept in the typed value but
+    /// not written to the frontend by this change, later changes without the filter write them.
+    pub fn change_with<C, O, E>(
+        &mut self,
+        options: &DiffOptions,
+        change: C,
+    ) -> Result<(O, Option<automerge_protocol::Change>), DocumentChangeError<E>>
+    where
+        E: Error,
+        C: FnOnce(&mut T) -> Result<O, E>,
+    {
+        self.change_inner(None, options.clone(), |t, _| change(t))
+    }
+
+    /// Apply a JSON Patch to the document.
+    ///
+    /// The patched value must still convert to the type of the document.
+    #[cfg(feature = "json")]
+    pub fn apply_json_patch(
+        &mut self,
+        patch: &json_patch::Patch,
+    ) -> Result<Option<automerge_protocol::Change>, ApplyJsonPatchError> {
+        let mut new_original = self.original.clone();
+        // write any migration first so that the patch applies to the value as it is shown
+        let mut changes = if self.outdated {
+            self.version_changes(&self.value, &mut new_original)
+                .map_err(DocumentChangeError::from)?
+        } else {
+            Vec::new()
+        };
+        changes.append(&mut crate::json::json_patch_changes(
+            patch,
+            &mut new_original,
+        )?);
+        let value =
+            T::from_automerge(&*migrated::<T>(&new_original).map_err(DocumentChangeError::from)?)
+                .map_err(DocumentChangeError::from)?;
+        changes.append(
+            &mut self
+                .version_changes(&value, &mut new_original)
+                .map_err(DocumentChangeError::from)?,
+        );
+        let redo = if self.undo.is_some() {
+            changes.clone()
+        } else {
+            Vec::new()
+        };
+        let change = self
+            .change_frontend(None, changes)
+            .map_err(DocumentChangeError::from)?;
+        self.value = value;
+        self.outdated = false;
+        let old = self.set_original(new_original);
+        let options = DiffOptions {
+            list_keys: self.list_keys.clone(),
+            ..DiffOptions::default()
+        };
+        self.record_undo(&old, redo, &options);
+        Ok(change)
     }
 
     /// Apply a patch to the frontend, updating the stored value in the process.
-    pub fn apply_patch(&mut self, patch: Patch) -> Result<(), ApplyPatchError<F::Error>> {
+    ///
+    /// Returns the changes which the patch made to the value. Steps recorded for undo which change
+    /// the same values as the patch, such as with changes from other actors, are forgotten, see
+    /// [`UndoManager`].
+    pub fn apply_patch(
+        &mut self,
+        patch: Patch,
+    ) -> Result<Vec<TypedChangeEvent>, ApplyPatchError<F::Error>> {
         self.frontend
             .apply_patch(patch)
             .map_err(ApplyPatchError::FrontendError)?;
-        self.refresh_value()?;
-        Ok(())
+        let old = self.refresh_value()?;
+        let events = TypedChangeEvent::from_report(&crate::diff_report(&self.original, &old));
+        if let Some(undo) = &mut self.undo {
+            let paths = events
+                .iter()
+                .map(|event| event.path().clone())
+                .collect::<Vec<_>>();
+            undo.forget_touching(&paths)
+        }
+        Ok(events)
     }
 
     /// Set the internal typed value to that obtained from the frontend.
     ///
-    /// This is intended to be used in case of interacting with the frontend directly.
-    fn refresh_value(&mut self) -> Result<(), ApplyPatchError<F::Error>> {
+    /// This is intended to be used in case of interacting with the frontend directly. Returns the
+    /// old automerge value.
+    fn refresh_value(&mut self) -> Result<Value, ApplyPatchError<F::Error>> {
         // TODO: change this to a new error type
-        self.original = self.get_root().map_err(ApplyPatchError::FrontendError)?;
-        self.value = T::from_automerge(&self.original)?;
+        let original = self.get_root().map_err(ApplyPatchError::FrontendError)?;
+        self.value = T::from_automerge(&*migrated::<T>(&original)?)?;
+        self.outdated = outdated(&self.value.to_automerge(), &original);
+        Ok(self.set_original(original))
+    }
+
+    /// Replace the stored automerge value, notifying the subscribers of any paths which changed.
+    /// Returns the old value.
+    fn set_original(&mut self, original: Value) -> Value {
+        let old = std::mem::replace(&mut self.original, original);
+        self.subscriptions.notify(&old, &self.original);
+        old
+    }
+
+    /// Get the changes which write the version markers of `value`, and any migrations, if
+    /// `original` is missing them, updating `original` to match. This is for changes which don't
+    /// diff the whole value.
+    fn version_changes(
+        &self,
+        value: &T,
+        original: &mut Value,
+    ) -> Result<Vec<LocalChange>, crate::DiffError> {
+        let current = value.to_automerge();
+        if !outdated(&current, original) {
+            return Ok(Vec::new());
+        }
+        let options = DiffOptions {
+            list_keys: self.list_keys.clone(),
+            ..DiffOptions::default()
+        };
+        let changes = crate::diff_values_with(&current, original, &options)?;
+        *original = current;
+        Ok(changes)
+    }
+
+    /// Record a local change which wrote `redo` to get from `old` to the current value, if undo
+    /// is enabled. It is undone by diffing back to `old` with the options the change used.
+    ///
+    /// Changes which can't be inverted, such as incrementing a counter when decreasing one is an
+    /// error, clear the recorded history as undoing the steps before them would no longer give
+    /// the values they recorded.
+    fn record_undo(&mut self, old: &Value, redo: Vec<LocalChange>, options: &DiffOptions) {
+        if let Some(undo) = &mut self.undo {
+            match crate::diff_values_with(old, &self.original, options) {
+                Ok(undo_changes) => undo.record(redo, undo_changes),
+                Err(_) => undo.clear(),
+            }
+        }
+    }
+
+    /// Set the undo manager which records local changes, or disable undo with `None`.
+    pub fn set_undo_manager(&mut self, undo: Option<UndoManager>) {
+        self.undo = undo
+    }
+
+    /// Get the undo manager, if undo is enabled.
+    pub const fn undo_manager(&self) -> Option<&UndoManager> {
+        self.undo.as_ref()
+    }
+
+    /// Get the undo manager mutably, such as to group changes, if undo is enabled.
+    pub fn undo_manager_mut(&mut self) -> Option<&mut UndoManager> {
+        self.undo.as_mut()
+    }
+
+    /// Undo the latest step of local changes by making a new change.
+    ///
+    /// Returns `Ok(None)` if there was nothing to undo. If the step no longer applies, such as
+    /// after another actor removed the changed values, it is dropped and the error returned.
+    pub fn undo(&mut self) -> Result<Option<automerge_protocol::Change>, DocumentChangeError> {
+        let step = match self.undo.as_mut().and_then(UndoManager::pop_undo) {
+            Some(step) => step,
+            None => return Ok(None),
+        };
+        let change = self.apply_local_changes(&step.undo)?;
+        if let Some(undo) = &mut self.undo {
+            undo.push_redo(step)
+        }
+        Ok(change)
+    }
+
+    /// Redo the latest undone step of local changes by making a new change.
+    ///
+    /// Returns `Ok(None)` if there was nothing to redo.
+    pub fn redo(&mut self) -> Result<Option<automerge_protocol::Change>, DocumentChangeError> {
+        let step = match self.undo.as_mut().and_then(UndoManager::pop_redo) {
+            Some(step) => step,
+            None => return Ok(None),
+        };
+        let change = self.apply_local_changes(&step.redo)?;
+        if let Some(undo) = &mut self.undo {
+            undo.push_undo(step)
+        }
+        Ok(change)
+    }
+
+    /// Apply changes to the value and the frontend, without recording them for undo.
+    fn apply_local_changes(
+        &mut self,
+        changes: &[LocalChange],
+    ) -> Result<Option<automerge_protocol::Change>, DocumentChangeError> {
+        let mut new_original = self.original.clone();
+        let mut all_changes = if self.outdated {
+            self.version_changes(&self.value, &mut new_original)?
+        } else {
+            Vec::new()
+        };
+        crate::apply_changes(&mut new_original, changes)?;
+        all_changes.extend_from_slice(changes);
+        let value = T::from_automerge(&*migrated::<T>(&new_original)?)?;
+        all_changes.append(&mut self.version_changes(&value, &mut new_original)?);
+        let change = self.change_frontend(None, all_changes)?;
+        self.value = value;
+        self.outdated = false;
+        self.set_original(new_original);
+        Ok(change)
+    }
+
+    /// Get the values written concurrently to `path`, converted to `U`, including the one which
+    /// [`Document::get`] shows.
+    ///
+    /// There is a conflict if there is more than one value, in no particular order. There are no
+    /// values if nothing is at the path. Each value is converted separately, so one which doesn't
+    /// convert to `U` doesn't hide the others.
+    pub fn conflicts<U>(
+        &self,
+        path: &Path,
+    ) -> Result<Vec<ConflictingValue<U>>, ConflictsError<F::Error>>
+    where
+        U: FromAutomerge,
+    {
+        let conflicts = self
+            .frontend
+            .get_conflicts(path)
+            .map_err(ConflictsError::FrontendError)?
+            .unwrap_or_default();
+        Ok(conflicts
+            .into_iter()
+            .map(|(op_id, value)| ConflictingValue {
+                actor_id: op_id.1.clone(),
+                op_id,
+                value: U::from_automerge(&value),
+            })
+            .collect())
+    }
+
+    /// Resolve the conflict at `path` by writing the chosen value, which replaces all of the
+    /// conflicting ones.
+    ///
+    /// The value is written even if it is the one [`Document::get`] already shows.
+    pub fn resolve_conflict<U>(
+        &mut self,
+        path: &Path,
+        value: &U,
+    ) -> Result<Option<automerge_protocol::Change>, DocumentChangeError>
+    where
+        U: ToAutomerge,
+    {
+        self.apply_local_changes(&[LocalChange::set(path.clone(), value.to_automerge())])
+    }
+
+    /// Subscribe to changes of the value at `path`, whether from local changes or applied patches.
+    ///
+    /// The callback is given the old and new values at the path, converted to `U`. Values which
+    /// don't exist, or don't convert to `U`, are given as `None`.
+    pub fn subscribe<U, C>(&mut self, path: Path, mut callback: C) -> SubscriptionId
+    where
+        U: FromAutomerge + 'static,
+        C: FnMut(Option<U>, Option<U>) + Send + 'static,
+    {
+        self.subscriptions.subscribe(
+            path,
+            Box::new(move |old, new| {
+                callback(
+                    old.and_then(|v| U::from_automerge(v).ok()),
+                    new.and_then(|v| U::from_automerge(v).ok()),
+                )
+            }),
+        )
+    }
+
+    /// Remove a subscription, returning whether it existed.
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        self.subscriptions.unsubscribe(id)
+    }
+}
+
+impl<T> Document<T, automerge::Frontend>
+where
+    T: Automergeable + Clone,
+{
+    /// Load a document from the bytes of a saved backend, returning it along with the backend.
+    ///
+    /// If the value was stored with an older schema version of `T`, the migration is applied to
+    /// the backend as a change, which can be sent to other peers with the rest.
+    pub fn load(bytes: Vec<u8>) -> Result<(Self, automerge::Backend), LoadDocumentError> {
+        let backend = automerge::Backend::load(bytes)?;
+        Self::from_backend(backend)
+    }
+
+    /// Load a document from a list of changes, returning it along with the backend they were
+    /// applied to.
+    pub fn from_changes(
+        changes: Vec<automerge::Change>,
+    ) -> Result<(Self, automerge::Backend), LoadDocumentError> {
+        let mut backend = automerge::Backend::new();
+        backend.apply_changes(changes)?;
+        Self::from_backend(backend)
+    }
+
+    /// Create the document from the backend, applying the migration of an older schema version
+    /// to the backend so that other peers get it too.
+    fn from_backend(
+        mut backend: automerge::Backend,
+    ) -> Result<(Self, automerge::Backend), LoadDocumentError> {
+        let mut frontend = automerge::Frontend::new();
+        frontend.apply_patch(backend.get_patch()?)?;
+        let (mut doc, change) = Self::try_new(frontend)?;
+        if let Some(change) = change {
+            let (patch, _) = backend.apply_local_change(change)?;
+            doc.apply_patch(patch)?;
+        }
+        Ok((doc, backend))
+    }
+
+    /// Replace the frontend with one loaded from the backend, such as to roll back a local change
+    /// which the backend rejected.
+    ///
+    /// The new frontend has a new actor id. Steps recorded for undo are forgotten as they may
+    /// refer to changes which aren't in the backend.
+    pub(crate) fn reload(&mut self, backend: &automerge::Backend) -> Result<(), LoadDocumentError> {
+        let mut frontend = automerge::Frontend::new();
+        frontend.apply_patch(backend.get_patch()?)?;
+        let original = Self::root_value(&frontend)?;
+        self.value = T::from_automerge(&*migrated::<T>(&original).map_err(NewDocumentError::from)?)
+            .map_err(NewDocumentError::from)?;
+        self.outdated = outdated(&self.value.to_automerge(), &original);
+        self.frontend = frontend;
+        self.set_original(original);
+        if let Some(undo) = &mut self.undo {
+            undo.clear()
+        }
         Ok(())
     }
 }
 
+impl<T, F> Document<T, F>
+where
+    T: Automergeable + Diff + Clone,
+    F: Frontend,
+{
+    /// Perform a change on the frontend, converting and diffing only the parts of the value which
+    /// changed.
+    ///
+    /// The changed parts are found with [`Diff`], so apart from cloning the value for the closure
+    /// the cost of a change is proportional to its size rather than the size of the document. Lists
+    /// whose length changed are converted and diffed as a whole.
+    pub fn change_incremental<C, O, E>(
+        &mut self,
+        change: C,
+    ) -> Result<(O, Option<automerge_protocol::Change>), DocumentChangeError<E>>
+    where
+        E: Error,
+        C: FnOnce(&mut T) -> Result<O, E>,
+    {
+        let mut new_t = self.value.clone();
+        let res = change(&mut new_t).map_err(DocumentChangeError::ChangeError)?;
+        let mut changed = Vec::new();
+        new_t.changed_values(&self.value, Path::root(), &mut changed);
+
+        let options = DiffOptions {
+            list_keys: self.list_keys.clone(),
+            ..DiffOptions::default()
+        };
+        let mut changes = Vec::new();
+        let mut new_original = None;
+        for (path, new) in changed {
+            let elements = path.clone().elements();
+            let parent = elements.split_last().map_or(&[][..], |(_, parent)| parent);
+            if self.outdated || crate::apply::resolve(&self.original, parent).is_none() {
+                // the value is inside one which doesn't exist yet, or the stored value is missing
+                // version markers, so diff the whole document
+                let original = new_t.to_automerge();
+                changes = crate::diff_values_with(&original, &self.original, &options)?;
+                new_original = Some(original);
+                break;
+            }
+            let old = crate::apply::resolve(&self.original, &elements);
+            changes.append(&mut crate::diff_with_path_and_options(
+                new.as_ref(),
+                old,
+                path,
+                &options,
+            )?);
+        }
+
+        let change = self.change_frontend(None, changes.clone())?;
+        // the markers are only written when the whole document is diffed
+        self.outdated &= new_original.is_none();
+        let old = if let Some(new_original) = new_original {
+            Some(self.set_original(new_original))
+        } else if self.subscriptions.is_empty() && self.undo.is_none() {
+            crate::apply_changes(&mut self.original, &changes)?;
+            None
+        } else {
+            let mut new_original = self.original.clone();
+            crate::apply_changes(&mut new_original, &changes)?;
+            Some(self.set_original(new_original))
+        };
+        if let Some(old) = old {
+            self.record_undo(&old, changes, &options)
+        }
+        self.value = new_t;
+        Ok((res, change))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use automerge::Frontend;
@@ -238,6 +975,314 @@ mod tests {
         }
     }
 
+    #[test]
+    fn try_new_with_wrong_type() {
+        assert!(matches!(
+            Document::<String, _>::try_new(Frontend::new()),
+            Err(NewDocumentError::FromError(_))
+        ));
+    }
+
+    #[test]
+    fn with_initial() {
+        #[derive(crate::Automergeable, Debug, Clone, Default, PartialEq)]
+        #[automergeable(crate_path = "crate")]
+        struct A {
+            list: Vec<String>,
+            #[automergeable(representation = "Text")]
+            some_text: String,
+        }
+
+        let initial = A {
+            list: vec!["a".to_owned()],
+            some_text: "hello".to_owned(),
+        };
+        let (mut doc, change) = Document::with_initial(Frontend::new(), initial.clone()).unwrap();
+        assert_eq!(doc.get(), &initial);
+
+        let mut back = automerge::Backend::new();
+        let (patch, _) = back.apply_local_change(change.unwrap()).unwrap();
+        // the frontend already had the value
+        assert_eq!(doc.apply_patch(patch).unwrap(), vec![]);
+        assert_eq!(doc.get(), &initial);
+        assert_eq!(
+            doc.frontend.get_value(&Path::root()),
+            Some(crate::ToAutomerge::to_automerge(&initial))
+        );
+    }
+
+    #[test]
+    fn load_and_from_changes() {
+        let mut initial = std::collections::HashMap::new();
+        initial.insert("a".to_owned(), 1_u64);
+        let (_, change) = Document::with_initial(Frontend::new(), initial.clone()).unwrap();
+        let mut back = automerge::Backend::new();
+        back.apply_local_change(change.unwrap()).unwrap();
+
+        let (doc, loaded) =
+            Document::<std::collections::HashMap<String, u64>, _>::load(back.save().unwrap())
+                .unwrap();
+        assert_eq!(doc.get(), &initial);
+        assert_eq!(loaded.get_heads(), back.get_heads());
+
+        let changes = back.get_changes(&[]).into_iter().cloned().collect();
+        let (doc, _) =
+            Document::<std::collections::HashMap<String, u64>, _>::from_changes(changes).unwrap();
+        assert_eq!(doc.get(), &initial);
+
+        assert!(matches!(
+            Document::<String, _>::load(back.save().unwrap()),
+            Err(LoadDocumentError::NewDocumentError(
+                NewDocumentError::FromError(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn migrations() {
+        #[derive(crate::Automergeable, Debug, Clone, Default, PartialEq)]
+        #[automergeable(crate_path = "crate", version = 2)]
+        struct Settings {
+            name: String,
+            size: u64,
+        }
+
+        impl Settings {
+            // version 1 called the name `title`
+            fn migrate_v1_to_v2(mut value: Value) -> Value {
+                if let Value::Map(map) = &mut value {
+                    if let Some(title) = map.remove("title") {
+                        map.insert("name".into(), title);
+                    }
+                }
+                value
+            }
+        }
+
+        let root = |entries: Vec<(&str, Value)>| {
+            Value::Map(entries.into_iter().map(|(k, v)| (k.into(), v)).collect())
+        };
+        let backend_with = |value: Value| {
+            let mut old = Document::<Value, _>::new(Frontend::new());
+            let ((), change) = old
+                .change::<_, _, automerge::InvalidChangeRequest>(|root| {
+                    *root = value;
+                    Ok(())
+                })
+                .unwrap();
+            let mut back = automerge::Backend::new();
+            back.apply_local_change(change.unwrap()).unwrap();
+            back
+        };
+
+        // a document written before the type was versioned
+        let back = backend_with(root(vec![
+            (
+                "title",
+                Value::Primitive(automerge::Primitive::Str("a".into())),
+            ),
+            ("size", Value::Primitive(automerge::Primitive::Uint(3))),
+        ]));
+        // new documents migrate the value but leave writing it to the next change
+        let mut frontend = Frontend::new();
+        frontend.apply_patch(back.get_patch().unwrap()).unwrap();
+        let mut doc = Document::<Settings, _>::new(frontend);
+        assert_eq!(doc.get().name, "a");
+        let ((), change) = doc
+            .change::<_, _, automerge::InvalidChangeRequest>(|_| Ok(()))
+            .unwrap();
+        assert!(change.is_some());
+        assert_eq!(
+            doc.frontend.get_value(&Path::root().key("name")),
+            Some(Value::Primitive(automerge::Primitive::Str("a".into())))
+        );
+
+        let (doc, back) = Document::<Settings, _>::load(back.save().unwrap()).unwrap();
+        assert_eq!(
+            doc.get(),
+            &Settings {
+                name: "a".to_owned(),
+                size: 3
+            }
+        );
+        // the migration is a change in the backend
+        assert_eq!(back.get_changes(&[]).len(), 2);
+        let (untyped, _) = Document::<Value, _>::load(back.save().unwrap()).unwrap();
+        assert_eq!(
+            untyped.get(),
+            &root(vec![
+                (
+                    "name",
+                    Value::Primitive(automerge::Primitive::Str("a".into()))
+                ),
+                ("size", Value::Primitive(automerge::Primitive::Uint(3))),
+                (
+                    crate::VERSION_KEY,
+                    Value::Primitive(automerge::Primitive::Uint(2))
+                ),
+            ])
+        );
+
+        // current documents aren't migrated again
+        let (_, back) = Document::<Settings, _>::load(back.save().unwrap()).unwrap();
+        assert_eq!(back.get_changes(&[]).len(), 2);
+
+        let back = backend_with(root(vec![(
+            crate::VERSION_KEY,
+            Value::Primitive(automerge::Primitive::Uint(3)),
+        )]));
+        assert!(matches!(
+            Document::<Settings, _>::load(back.save().unwrap()),
+            Err(LoadDocumentError::NewDocumentError(
+                NewDocumentError::MigrationError(crate::MigrationError::NewerVersion {
+                    found: 3,
+                    current: 2
+                })
+            ))
+        ));
+    }
+
+    #[derive(crate::Automergeable, crate::Diff, Debug, Clone, Default, PartialEq)]
+    #[automergeable(crate_path = "crate", version = 2)]
+    struct Sized {
+        size: i64,
+    }
+
+    impl Sized {
+        // version 1 stored the size in kilobytes
+        fn migrate_v1_to_v2(mut value: Value) -> Value {
+            if let Value::Map(map) = &mut value {
+                if let Some(Value::Primitive(automerge::Primitive::Int(size))) = map.get_mut("size")
+                {
+                    *size *= 1024
+                }
+            }
+            value
+        }
+    }
+
+    /// Save the changes to a backend and load it again, checking that it wasn't migrated again.
+    fn reload_sized(doc: &mut Document<Sized, Frontend>, change: automerge_protocol::Change) {
+        let mut back = automerge::Backend::new();
+        let (patch, _) = back.apply_local_change(change).unwrap();
+        doc.apply_patch(patch).unwrap();
+        let (loaded, back) = Document::<Sized, _>::load(back.save().unwrap()).unwrap();
+        assert_eq!(loaded.get(), doc.get());
+        assert_eq!(back.get_changes(&[]).len(), 1);
+    }
+
+    #[test]
+    fn incremental_changes_write_version() {
+        let mut doc = Document::<Sized, _>::new(Frontend::new());
+        let ((), change) = doc
+            .change_incremental::<_, _, automerge::InvalidChangeRequest>(|s| {
+                s.size = 3;
+                Ok(())
+            })
+            .unwrap();
+        reload_sized(&mut doc, change.unwrap());
+        assert_eq!(doc.get().size, 3);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_patch_writes_version() {
+        let mut doc = Document::<Sized, _>::new(Frontend::new());
+        let patch = json_patch::from_value(serde_json::json!([
+            {"op": "add", "path": "/size", "value": 3},
+        ]))
+        .unwrap();
+        let change = doc.apply_json_patch(&patch).unwrap();
+        reload_sized(&mut doc, change.unwrap());
+        assert_eq!(doc.get().size, 3);
+    }
+
+    #[test]
+    fn nested_migrations() {
+        #[derive(crate::Automergeable, Debug, Clone, Default, PartialEq)]
+        #[automergeable(crate_path = "crate", version = 2)]
+        struct Item {
+            name: String,
+        }
+
+        impl Item {
+            // version 1 called the name `title`
+            fn migrate_v1_to_v2(mut value: Value) -> Value {
+                if let Value::Map(map) = &mut value {
+                    if let Some(title) = map.remove("title") {
+                        map.insert("name".into(), title);
+                    }
+                }
+                value
+            }
+        }
+
+        #[derive(crate::Automergeable, Debug, Clone, Default, PartialEq)]
+        #[automergeable(crate_path = "crate")]
+        struct Items {
+            items: Vec<Item>,
+        }
+
+        let string = |s: &str| Value::Primitive(automerge::Primitive::Str(s.into()));
+        let map = |entries: Vec<(&str, Value)>| {
+            Value::Map(entries.into_iter().map(|(k, v)| (k.into(), v)).collect())
+        };
+        // a document written before the items were versioned
+        let mut old = Document::<Value, _>::new(Frontend::new());
+        let ((), change) = old
+            .change::<_, _, automerge::InvalidChangeRequest>(|root| {
+                *root = map(vec![(
+                    "items",
+                    Value::List(vec![
+                        map(vec![("title", string("a"))]),
+                        map(vec![("title", string("b"))]),
+                    ]),
+                )]);
+                Ok(())
+            })
+            .unwrap();
+        let mut back = automerge::Backend::new();
+        back.apply_local_change(change.unwrap()).unwrap();
+        let frontend = |back: &automerge::Backend| {
+            let mut frontend = Frontend::new();
+            frontend.apply_patch(back.get_patch().unwrap()).unwrap();
+            frontend
+        };
+
+        let (doc, change) = Document::<Items, _>::try_new(frontend(&back)).unwrap();
+        let item = |name: &str| Item {
+            name: name.to_owned(),
+        };
+        assert_eq!(
+            doc.get(),
+            &Items {
+                items: vec![item("a"), item("b")]
+            }
+        );
+        // the migration is written straight away
+        back.apply_local_change(change.unwrap()).unwrap();
+        let (untyped, _) = Document::<Value, _>::load(back.save().unwrap()).unwrap();
+        let version = Value::Primitive(automerge::Primitive::Uint(2));
+        assert_eq!(
+            untyped.get(),
+            &map(vec![(
+                "items",
+                Value::List(vec![
+                    map(vec![
+                        ("name", string("a")),
+                        (crate::VERSION_KEY, version.clone())
+                    ]),
+                    map(vec![("name", string("b")), (crate::VERSION_KEY, version)]),
+                ]),
+            )])
+        );
+
+        // current documents aren't migrated again
+        let (doc, change) = Document::<Items, _>::try_new(frontend(&back)).unwrap();
+        assert!(change.is_none());
+        assert_eq!(doc.get().items, vec![item("a"), item("b")]);
+    }
+
     #[test]
     fn create_from_empty_then_add_some_fields() {
         #[derive(crate::Automergeable, Debug, Clone, Default)]
@@ -330,4 +1375,529 @@ mod tests {
             doc.apply_patch(patch).unwrap();
         }
     }
+
+    #[test]
+    fn change_with_options() {
+        #[derive(crate::Automergeable, Debug, Clone, Default)]
+        #[automergeable(crate_path = "crate")]
+        struct A {
+            #[automergeable(representation = "Counter")]
+            a_counter: i64,
+        }
+
+        let mut doc = Document::<A, _>::new(Frontend::new());
+        let mut back = automerge::Backend::new();
+        let options = DiffOptions {
+            counter_decrease: crate::CounterPolicy::Error,
+            ..DiffOptions::default()
+        };
+
+        let ((), change) = doc
+            .change_with::<_, _, automerge::InvalidChangeRequest>(&options, |t| {
+                t.a_counter += 2;
+                Ok(())
+            })
+            .unwrap();
+        if let Some(change) = change {
+            let (patch, _) = back.apply_local_change(change).unwrap();
+            doc.apply_patch(patch).unwrap();
+        }
+
+        let res = doc.change_with::<_, _, automerge::InvalidChangeRequest>(&options, |t| {
+            t.a_counter -= 1;
+            Ok(())
+        });
+        assert!(matches!(
+            res,
+            Err(DocumentChangeError::InvalidChangeRequest(
+                automerge::InvalidChangeRequest::CannotOverwriteCounter { .. }
+            ))
+        ));
+        assert_eq!(doc.get().a_counter, 2);
+    }
+
+    #[test]
+    fn change_with_filter_skips_excluded_fields() {
+        #[derive(crate::Automergeable, Debug, Clone, Default)]
+        #[automergeable(crate_path = "crate")]
+        struct A {
+            shared: u64,
+            cache: u64,
+        }
+
+        let mut doc = Document::<A, _>::new(Frontend::new());
+        let mut options = DiffOptions::default();
+        options.filter.exclude(Path::root().key("cache"));
+
+        doc.change_with::<_, _, automerge::InvalidChangeRequest>(&options, |t| {
+            t.shared = 1;
+            t.cache = 2;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(doc.get().cache, 2);
+        assert_eq!(
+            doc.frontend.get_value(&Path::root().key("shared")),
+            Some(Value::Primitive(automerge::Primitive::Uint(1)))
+        );
+        assert_eq!(doc.frontend.get_value(&Path::root().key("cache")), None);
+
+        // the filter only applies to the change it was given to
+        doc.change::<_, _, automerge::InvalidChangeRequest>(|t| {
+            t.shared = 3;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            doc.frontend.get_value(&Path::root().key("cache")),
+            Some(Value::Primitive(automerge::Primitive::Uint(2)))
+        );
+    }
+
+    #[test]
+    fn subscriptions() {
+        #[derive(crate::Automergeable, Debug, Clone, Default)]
+        #[automergeable(crate_path = "crate")]
+        struct A {
+            a: u64,
+            b: B,
+        }
+
+        #[derive(crate::Automergeable, Debug, Clone, Default)]
+        #[automergeable(crate_path = "crate")]
+        struct B {
+            inner: u64,
+        }
+
+        let (mut doc, _) = Document::with_initial(Frontend::new(), A::default()).unwrap();
+        let calls = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sub_calls = calls.clone();
+        let id = doc.subscribe(Path::root().key("b").key("inner"), move |old, new| {
+            sub_calls.lock().unwrap().push((old, new))
+        });
+
+        doc.change::<_, _, automerge::InvalidChangeRequest>(|t| {
+            t.a = 1;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(*calls.lock().unwrap(), vec![]);
+
+        let ((), change) = doc
+            .change::<_, _, automerge::InvalidChangeRequest>(|t| {
+                t.b.inner = 2;
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(*calls.lock().unwrap(), vec![(Some(0), Some(2_u64))]);
+
+        // patches for the same value don't notify again
+        let mut back = automerge::Backend::new();
+        let (patch, _) = back.apply_local_change(change.unwrap()).unwrap();
+        doc.apply_patch(patch).unwrap();
+        assert_eq!(calls.lock().unwrap().len(), 1);
+
+        assert!(doc.unsubscribe(id));
+        assert!(!doc.unsubscribe(id));
+        doc.change::<_, _, automerge::InvalidChangeRequest>(|t| {
+            t.b.inner = 3;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(calls.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn apply_patch_events() {
+        let mut initial = std::collections::HashMap::new();
+        initial.insert("a".to_owned(), 1_u64);
+        initial.insert("b".to_owned(), 2);
+        let (mut ours, change) = Document::with_initial(Frontend::new(), initial).unwrap();
+        let mut back = automerge::Backend::new();
+        back.apply_local_change(change.unwrap()).unwrap();
+        let (mut theirs, _) =
+            Document::<std::collections::HashMap<String, u64>, _>::load(back.save().unwrap())
+                .unwrap();
+
+        let ((), change) = ours
+            .change::<_, _, automerge::InvalidChangeRequest>(|map| {
+                map.insert("a".to_owned(), 3);
+                map.remove("b");
+                map.insert("c".to_owned(), 4);
+                Ok(())
+            })
+            .unwrap();
+        let (patch, _) = back.apply_local_change(change.unwrap()).unwrap();
+        let mut events = theirs.apply_patch(patch).unwrap();
+        events.sort_by_key(|e| format!("{:?}", e));
+        let uint = |u| Value::Primitive(automerge::Primitive::Uint(u));
+        assert_eq!(
+            events,
+            vec![
+                TypedChangeEvent::Inserted {
+                    path: Path::root().key("c"),
+                    value: uint(4),
+                },
+                TypedChangeEvent::Removed {
+                    path: Path::root().key("b"),
+                    value: uint(2),
+                },
+                TypedChangeEvent::Updated {
+                    path: Path::root().key("a"),
+                    old: uint(1),
+                    new: uint(3),
+                },
+            ]
+        );
+        assert_eq!(theirs.get(), ours.get());
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        #[derive(crate::Automergeable, Debug, Clone, Default, PartialEq)]
+        #[automergeable(crate_path = "crate")]
+        struct A {
+            list: Vec<String>,
+            #[automergeable(representation = "Counter")]
+            counter: i64,
+        }
+
+        let (mut doc, change) = Document::with_initial(Frontend::new(), A::default()).unwrap();
+        let mut back = automerge::Backend::new();
+        back.apply_local_change(change.unwrap()).unwrap();
+        doc.set_undo_manager(Some(crate::UndoManager::with_max_depth(2)));
+
+        let mut change = |doc: &mut Document<A, Frontend>, f: fn(&mut A)| {
+            let ((), change) = doc
+                .change::<_, _, automerge::InvalidChangeRequest>(|a| {
+                    f(a);
+                    Ok(())
+                })
+                .unwrap();
+            let (patch, _) = back.apply_local_change(change.unwrap()).unwrap();
+            doc.apply_patch(patch).unwrap();
+        };
+        change(&mut doc, |a| a.list.push("a".to_owned()));
+        change(&mut doc, |a| a.counter += 2);
+        let after = doc.get().clone();
+
+        assert!(doc.undo().unwrap().is_some());
+        assert_eq!(doc.get().counter, 0);
+        assert!(doc.undo().unwrap().is_some());
+        assert_eq!(doc.get(), &A::default());
+        assert!(doc.undo().unwrap().is_none());
+        assert!(doc.redo().unwrap().is_some());
+        assert!(doc.redo().unwrap().is_some());
+        assert_eq!(doc.get(), &after);
+        assert!(doc.redo().unwrap().is_none());
+        assert_eq!(
+            doc.frontend.get_value(&Path::root()),
+            Some(crate::ToAutomerge::to_automerge(&after))
+        );
+
+        // grouped changes are undone together and old steps are dropped past the max depth
+        let undo = doc.undo_manager_mut().unwrap();
+        undo.begin_group();
+        change(&mut doc, |a| a.list.push("b".to_owned()));
+        change(&mut doc, |a| a.list.push("c".to_owned()));
+        doc.undo_manager_mut().unwrap().end_group();
+        change(&mut doc, |a| a.counter += 1);
+        assert!(doc.undo().unwrap().is_some());
+        assert!(doc.undo().unwrap().is_some());
+        assert_eq!(doc.get(), &after);
+        assert!(!doc.undo_manager().unwrap().can_undo());
+        assert!(doc.undo_manager().unwrap().can_redo());
+
+        // a new change clears the steps to redo
+        change(&mut doc, |a| a.counter += 1);
+        assert!(!doc.undo_manager().unwrap().can_redo());
+
+        // a change which can't be undone clears the history rather than leaving steps which undo
+        // to values it never had
+        assert!(doc.undo_manager().unwrap().can_undo());
+        let options = DiffOptions {
+            counter_decrease: crate::CounterPolicy::Error,
+            ..DiffOptions::default()
+        };
+        let ((), change) = doc
+            .change_with::<_, _, automerge::InvalidChangeRequest>(&options, |a| {
+                a.counter += 1;
+                Ok(())
+            })
+            .unwrap();
+        let (patch, _) = back.apply_local_change(change.unwrap()).unwrap();
+        doc.apply_patch(patch).unwrap();
+        assert!(!doc.undo_manager().unwrap().can_undo());
+        assert!(doc.undo().unwrap().is_none());
+    }
+
+    #[test]
+    fn remote_patch_keeps_unrelated_undo() {
+        let mut initial = std::collections::HashMap::new();
+        initial.insert("a".to_owned(), 1_u64);
+        let (mut ours, change) = Document::with_initial(Frontend::new(), initial).unwrap();
+        let mut back = automerge::Backend::new();
+        back.apply_local_change(change.unwrap()).unwrap();
+        let (mut theirs, _) =
+            Document::<std::collections::HashMap<String, u64>, _>::load(back.save().unwrap())
+                .unwrap();
+        ours.set_undo_manager(Some(crate::UndoManager::new()));
+
+        let ((), change) = ours
+            .change::<_, _, automerge::InvalidChangeRequest>(|map| {
+                map.insert("a".to_owned(), 2);
+                Ok(())
+            })
+            .unwrap();
+        let (patch, _) = back.apply_local_change(change.unwrap()).unwrap();
+        theirs.apply_patch(patch.clone()).unwrap();
+        // our own patch doesn't change the value so the step is kept
+        assert!(ours.apply_patch(patch).unwrap().is_empty());
+        assert!(ours.undo_manager().unwrap().can_undo());
+
+        // a change from another actor to other values keeps the step
+        let ((), change) = theirs
+            .change::<_, _, automerge::InvalidChangeRequest>(|map| {
+                map.insert("b".to_owned(), 3);
+                Ok(())
+            })
+            .unwrap();
+        let (patch, _) = back.apply_local_change(change.unwrap()).unwrap();
+        theirs.apply_patch(patch.clone()).unwrap();
+        assert!(!ours.apply_patch(patch).unwrap().is_empty());
+        assert!(ours.undo_manager().unwrap().can_undo());
+        let change = ours.undo().unwrap();
+        assert_eq!(ours.get().get("a"), Some(&1));
+        assert_eq!(ours.get().get("b"), Some(&3));
+        let (patch, _) = back.apply_local_change(change.unwrap()).unwrap();
+        theirs.apply_patch(patch.clone()).unwrap();
+        ours.apply_patch(patch).unwrap();
+        assert!(ours.undo_manager().unwrap().can_redo());
+
+        // but one to the same values would be undone too so the step is forgotten
+        let ((), change) = theirs
+            .change::<_, _, automerge::InvalidChangeRequest>(|map| {
+                map.insert("a".to_owned(), 4);
+                Ok(())
+            })
+            .unwrap();
+        let (patch, _) = back.apply_local_change(change.unwrap()).unwrap();
+        assert!(!ours.apply_patch(patch).unwrap().is_empty());
+        assert!(!ours.undo_manager().unwrap().can_redo());
+        assert!(ours.redo().unwrap().is_none());
+        assert_eq!(ours.get().get("a"), Some(&4));
+    }
+
+    #[test]
+    fn conflicts() {
+        type Map = std::collections::HashMap<String, u64>;
+        let mut initial = Map::new();
+        initial.insert("a".to_owned(), 1);
+        let (mut ours, change) = Document::with_initial(Frontend::new(), initial).unwrap();
+        let mut our_back = automerge::Backend::new();
+        let (patch, _) = our_back.apply_local_change(change.unwrap()).unwrap();
+        ours.apply_patch(patch).unwrap();
+        let (mut theirs, mut their_back) =
+            Document::<Map, _>::load(our_back.save().unwrap()).unwrap();
+
+        let conflicts = ours.conflicts::<u64>(&Path::root().key("a")).unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert!(ours
+            .conflicts::<u64>(&Path::root().key("b"))
+            .unwrap()
+            .is_empty());
+
+        // both actors write the key concurrently then exchange their changes
+        let write = |doc: &mut Document<Map, Frontend>, back: &mut automerge::Backend, a| {
+            let ((), change) = doc
+                .change::<_, _, automerge::InvalidChangeRequest>(|map| {
+                    map.insert("a".to_owned(), a);
+                    Ok(())
+                })
+                .unwrap();
+            let (patch, change) = back.apply_local_change(change.unwrap()).unwrap();
+            doc.apply_patch(patch).unwrap();
+            change
+        };
+        let our_change = write(&mut ours, &mut our_back, 2);
+        let their_change = write(&mut theirs, &mut their_back, 3);
+        ours.apply_patch(our_back.apply_changes(vec![their_change]).unwrap())
+            .unwrap();
+        theirs
+            .apply_patch(their_back.apply_changes(vec![our_change]).unwrap())
+            .unwrap();
+
+        let mut conflicts = ours.conflicts::<u64>(&Path::root().key("a")).unwrap();
+        conflicts.sort_by_key(|c| *c.value.as_ref().unwrap());
+        assert_eq!(conflicts.len(), 2);
+        assert_eq!(conflicts[0].value.as_ref().unwrap(), &2);
+        assert_eq!(conflicts[0].actor_id, ours.frontend.actor_id);
+        assert_eq!(conflicts[1].value.as_ref().unwrap(), &3);
+        assert_eq!(conflicts[1].actor_id, theirs.frontend.actor_id);
+        assert_eq!(
+            theirs
+                .conflicts::<u64>(&Path::root().key("a"))
+                .unwrap()
+                .len(),
+            2
+        );
+        // values which don't convert are reported without hiding the others
+        let conflicts = ours.conflicts::<String>(&Path::root().key("a")).unwrap();
+        assert_eq!(conflicts.len(), 2);
+        assert!(conflicts.iter().all(|c| c.value.is_err()));
+
+        let change = ours
+            .resolve_conflict(&Path::root().key("a"), &3_u64)
+            .unwrap();
+        let (patch, change) = our_back.apply_local_change(change.unwrap()).unwrap();
+        ours.apply_patch(patch).unwrap();
+        theirs
+            .apply_patch(their_back.apply_changes(vec![change]).unwrap())
+            .unwrap();
+        for doc in &[&ours, &theirs] {
+            let conflicts = doc.conflicts::<u64>(&Path::root().key("a")).unwrap();
+            assert_eq!(conflicts.len(), 1);
+            assert_eq!(conflicts[0].value.as_ref().unwrap(), &3);
+            assert_eq!(conflicts[0].actor_id, ours.frontend.actor_id);
+            assert_eq!(doc.get().get("a"), Some(&3));
+        }
+        // the value is written even if it's already shown
+        assert!(ours
+            .resolve_conflict(&Path::root().key("a"), &3_u64)
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn change_incremental_matches_change() {
+        #[derive(crate::Automergeable, crate::Diff, Debug, Clone, Default, PartialEq)]
+        #[automergeable(crate_path = "crate")]
+        struct A {
+            list: Vec<String>,
+            others: std::collections::HashMap<String, String>,
+            #[automergeable(representation = "Text")]
+            some_text: String,
+            b: B,
+            bs: Vec<B>,
+        }
+
+        #[derive(crate::Automergeable, crate::Diff, Debug, Clone, Default, PartialEq)]
+        #[automergeable(crate_path = "crate")]
+        struct B {
+            inner: u64,
+        }
+
+        let mut doc = Document::<A, _>::new(Frontend::new());
+        let mut incremental_doc = Document::<A, _>::new(Frontend::new());
+        let changes: Vec<fn(&mut A)> = vec![
+            |a| a.b.inner = 1,
+            |a| {
+                a.list.push("a".to_owned());
+                a.some_text = "hello".to_owned();
+            },
+            |a| {
+                a.others.insert("a".to_owned(), "b".to_owned());
+                a.some_text = "hello world".to_owned();
+            },
+            |a| {
+                a.others.clear();
+                a.b.inner += 1;
+            },
+            |a| a.list[0] = "b".to_owned(),
+            |a| a.bs.push(B::default()),
+            |a| a.bs[0].inner = 2,
+        ];
+        for change in changes {
+            doc.change::<_, _, automerge::InvalidChangeRequest>(|a| {
+                change(a);
+                Ok(())
+            })
+            .unwrap();
+            incremental_doc
+                .change_incremental::<_, _, automerge::InvalidChangeRequest>(|a| {
+                    change(a);
+                    Ok(())
+                })
+                .unwrap();
+            assert_eq!(incremental_doc.get(), doc.get());
+            assert_eq!(incremental_doc.original, doc.original);
+            assert_eq!(
+                incremental_doc.frontend.get_value(&Path::root()),
+                doc.frontend.get_value(&Path::root())
+            );
+        }
+    }
+
+    #[test]
+    fn change_with_cursors() {
+        #[derive(crate::Automergeable, Debug, Clone, Default)]
+        #[automergeable(crate_path = "crate")]
+        struct A {
+            #[automergeable(representation = "Text")]
+            text: String,
+            cursor: Option<crate::Cursor>,
+            #[automergeable(representation = "Cursor")]
+            raw_cursor: Option<automerge_frontend::Cursor>,
+        }
+
+        let mut doc = Document::<A, _>::new(Frontend::new());
+        doc.change::<_, _, automerge::InvalidChangeRequest>(|t| {
+            t.text = "hello".to_owned();
+            Ok(())
+        })
+        .unwrap();
+
+        let text = Path::root().key("text");
+        let ((), change) = doc
+            .change_with_cursors::<_, _, automerge::InvalidChangeRequest>(|t, cursors| {
+                assert_eq!(cursors.cursor(&Path::root().key("cursor")), None);
+                t.cursor = cursors.cursor(&text.clone().index(1));
+                t.raw_cursor = cursors
+                    .cursor(&text.clone().index(3))
+                    .map(crate::Cursor::into_inner);
+                Ok(())
+            })
+            .unwrap();
+        assert!(change.is_some());
+        assert_eq!(doc.get().cursor.as_ref().map(crate::Cursor::index), Some(1));
+        assert_eq!(doc.get().raw_cursor.as_ref().map(|c| c.index), Some(3));
+        assert_eq!(
+            doc.frontend.get_value(&Path::root().key("cursor")),
+            doc.get()
+                .cursor
+                .as_ref()
+                .map(crate::ToAutomerge::to_automerge)
+        );
+
+        // unchanged cursors aren't set again
+        let ((), change) = doc
+            .change::<_, _, automerge::InvalidChangeRequest>(|_| Ok(()))
+            .unwrap();
+        assert_eq!(change, None);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn apply_json_patch() {
+        let mut doc = Document::<std::collections::HashMap<String, i64>, _>::new(Frontend::new());
+        let patch = json_patch::from_value(serde_json::json!([
+            {"op": "add", "path": "/a", "value": 1},
+            {"op": "copy", "from": "/a", "path": "/b"},
+        ]))
+        .unwrap();
+        doc.apply_json_patch(&patch).unwrap();
+        assert_eq!(doc.get().get("b"), Some(&1));
+
+        let patch = json_patch::from_value(serde_json::json!([
+            {"op": "add", "path": "/c", "value": "not a number"},
+        ]))
+        .unwrap();
+        assert!(matches!(
+            doc.apply_json_patch(&patch),
+            Err(ApplyJsonPatchError::ChangeError(
+                DocumentChangeError::FromError(_)
+            ))
+        ));
+        assert_eq!(doc.get().len(), 2);
+    }
 }
@@ -0,0 +1,459 @@
+use std::convert::TryInto;
+
+use ::json_patch::{AddOperation, Patch, PatchOperation, RemoveOperation, ReplaceOperation};
+use automerge::{LocalChange, LocalOperation, Path, PathElement, Primitive, Value};
+
+use crate::{apply::resolve, apply_change, ApplyChangeError, ToAutomerge};
+
+/// An error type for converting between [`LocalChange`]s and JSON Patches.
+#[derive(Debug, thiserror::Error)]
+pub enum JsonPatchError {
+    /// JSON has no counters, so they can't be set or incremented in a JSON Patch.
+    #[error("the counter at {path} has no JSON Patch equivalent")]
+    Counter {
+        /// The path to the counter.
+        path: Path,
+    },
+    /// JSON has no text objects, so they can't be set or spliced in a JSON Patch.
+    #[error("the text at {path} has no JSON Patch equivalent")]
+    Text {
+        /// The path to the text.
+        path: Path,
+    },
+    /// The value has no JSON equivalent, such as a cursor or bytes.
+    #[error("the value at {path} has no JSON equivalent")]
+    UnsupportedValue {
+        /// The path to the value.
+        path: Path,
+    },
+    /// The pointer isn't a valid JSON Pointer or doesn't fit the value it points into.
+    #[error("invalid JSON pointer {pointer:?}")]
+    InvalidPointer {
+        /// The pointer.
+        pointer: String,
+    },
+    /// There is no value at the pointer.
+    #[error("no value at {pointer:?}")]
+    NoSuchPath {
+        /// The pointer.
+        pointer: String,
+    },
+    /// A test operation found a different value.
+    #[error("the value at {pointer:?} didn't match the test")]
+    TestFailed {
+        /// The pointer to the value.
+        pointer: String,
+    },
+    /// The root couldn't be replaced.
+    #[error(transparent)]
+    DiffError(#[from] crate::DiffError),
+    /// The change couldn't be applied to the value.
+    #[error(transparent)]
+    ApplyChangeError(#[from] ApplyChangeError),
+}
+
+/// Convert the changes from `old` into a JSON Patch.
+///
+/// The old value is needed to tell changes to text apart from those to lists. Setting a map key
+/// becomes an `add`, setting a list element or the root a `replace`, inserting an `add` and
+/// deleting a `remove`. Timestamps become plain numbers.
+pub fn to_json_patch(changes: &[LocalChange], old: &Value) -> Result<Patch, JsonPatchError> {
+    let mut value = old.clone();
+    let mut operations = Vec::with_capacity(changes.len());
+    for change in changes {
+        let path = change.path();
+        let elements = path.clone().elements();
+        let parent = elements
+            .split_last()
+            .and_then(|(_, parent)| resolve(&value, parent));
+        if let Some(Value::Text(_)) = parent {
+            return Err(JsonPatchError::Text {
+                path: path.clone().parent(),
+            });
+        }
+        let pointer = to_pointer(&elements);
+        let operation = match change.operation() {
+            LocalOperation::Set(new) => {
+                let value = to_json(new, path)?;
+                if let Some(PathElement::Index(_)) | None = elements.last() {
+                    PatchOperation::Replace(ReplaceOperation {
+                        path: pointer,
+                        value,
+                    })
+                } else {
+                    PatchOperation::Add(AddOperation {
+                        path: pointer,
+                        value,
+                    })
+                }
+            }
+            LocalOperation::Insert(new) => PatchOperation::Add(AddOperation {
+                path: pointer,
+                value: to_json(new, path)?,
+            }),
+            LocalOperation::Delete => PatchOperation::Remove(RemoveOperation { path: pointer }),
+            LocalOperation::Increment(_) => {
+                return Err(JsonPatchError::Counter { path: path.clone() })
+            }
+        };
+        operations.push(operation);
+        apply(&mut value, change)?;
+    }
+    Ok(Patch(operations))
+}
+
+/// Convert a JSON Patch into the [`LocalChange`]s which make the same changes to the value.
+///
+/// The value is needed to resolve array indices and the sources of `move` and `copy` operations,
+/// and to check `test` operations. Replacing the root is done by diffing the new root against
+/// the old one.
+pub fn from_json_patch(patch: &Patch, value: &Value) -> Result<Vec<LocalChange>, JsonPatchError> {
+    let mut value = value.clone();
+    json_patch_changes(patch, &mut value)
+}
+
+/// Convert a JSON Patch into [`LocalChange`]s, applying them to the value as they are made.
+pub fn json_patch_changes(
+    patch: &Patch,
+    value: &mut Value,
+) -> Result<Vec<LocalChange>, JsonPatchError> {
+    let mut changes = Vec::new();
+    for operation in &patch.0 {
+        let new_changes = match operation {
+            PatchOperation::Add(op) => add(value, &op.path, op.value.to_automerge())?,
+            PatchOperation::Remove(op) => remove(value, &op.path)?,
+            PatchOperation::Replace(op) => {
+                let (path, old) = existing(value, &op.path)?;
+                if let Value::Primitive(Primitive::Counter(_)) = old {
+                    return Err(JsonPatchError::Counter { path });
+                }
+                set(value, path, op.value.to_automerge())?
+            }
+            PatchOperation::Move(op) => {
+                let (_, from) = existing(value, &op.from)?;
+                let from = from.clone();
+                let mut changes = remove(value, &op.from)?;
+                changes.append(&mut add(value, &op.path, from)?);
+                changes
+            }
+            PatchOperation::Copy(op) => {
+                let (_, from) = existing(value, &op.from)?;
+                let from = from.clone();
+                add(value, &op.path, from)?
+            }
+            PatchOperation::Test(op) => {
+                let (path, current) = existing(value, &op.path)?;
+                if to_json(current, &path)? != op.value {
+                    return Err(JsonPatchError::TestFailed {
+                        pointer: op.path.clone(),
+                    });
+                }
+                Vec::new()
+            }
+        };
+        changes.extend(new_changes);
+    }
+    Ok(changes)
+}
+
+/// Add the new value at the pointer, inserting it into arrays.
+fn add(value: &mut Value, pointer: &str, new: Value) -> Result<Vec<LocalChange>, JsonPatchError> {
+    let change = match resolve_pointer(value, pointer)? {
+        None => return set(value, Path::root(), new),
+        Some((parent_path, Value::List(vec), token)) => {
+            let index = if token == "-" {
+                vec.len()
+            } else {
+                parse_index(&token, pointer)?
+            };
+            if index > vec.len() {
+                return Err(no_such_path(pointer));
+            }
+            LocalChange::insert(parent_path.index(index.try_into().unwrap()), new)
+        }
+        Some((parent_path, _, token)) => LocalChange::set(parent_path.key(token), new),
+    };
+    apply(value, &change)?;
+    Ok(vec![change])
+}
+
+/// Remove the value at the pointer.
+fn remove(value: &mut Value, pointer: &str) -> Result<Vec<LocalChange>, JsonPatchError> {
+    let (path, _) = existing(value, pointer)?;
+    if path == Path::root() {
+        return Err(JsonPatchError::InvalidPointer {
+            pointer: pointer.to_owned(),
+        });
+    }
+    let change = LocalChange::delete(path);
+    apply(value, &change)?;
+    Ok(vec![change])
+}
+
+/// Set the value at the path, diffing the root rather than replacing it.
+fn set(value: &mut Value, path: Path, new: Value) -> Result<Vec<LocalChange>, JsonPatchError> {
+    if path == Path::root() {
+        let changes = crate::diff_values_with(&new, value, &crate::DiffOptions::default())
+            .map_err(|e| match e {
+                crate::DiffError::InvalidChangeRequest(
+                    automerge::InvalidChangeRequest::CannotOverwriteCounter { path },
+                ) => JsonPatchError::Counter { path },
+                e => e.into(),
+            })?;
+        *value = new;
+        return Ok(changes);
+    }
+    let change = LocalChange::set(path, new);
+    apply(value, &change)?;
+    Ok(vec![change])
+}
+
+/// Apply the change to the value, reporting overwritten counters like other changes to counters.
+fn apply(value: &mut Value, change: &LocalChange) -> Result<(), JsonPatchError> {
+    apply_change(value, change).map_err(|e| match e {
+        ApplyChangeError::CannotOverwriteCounter { path } => JsonPatchError::Counter { path },
+        e => e.into(),
+    })
+}
+
+/// Find the path to the existing value at the pointer and the value itself.
+fn existing<'a>(value: &'a Value, pointer: &str) -> Result<(Path, &'a Value), JsonPatchError> {
+    let (parent_path, parent, token) = match resolve_pointer(value, pointer)? {
+        Some(resolved) => resolved,
+        None => return Ok((Path::root(), value)),
+    };
+    let (path, existing) = match parent {
+        Value::List(vec) => {
+            let index = parse_index(&token, pointer)?;
+            (parent_path.index(index.try_into().unwrap()), vec.get(index))
+        }
+        Value::Map(map) | Value::Table(map) => {
+            let existing = map.get(token.as_str());
+            (parent_path.key(token), existing)
+        }
+        _ => return Err(no_such_path(pointer)),
+    };
+    Ok((path, existing.ok_or_else(|| no_such_path(pointer))?))
+}
+
+/// Find the map, table or list which a JSON Pointer points into, giving its path, the container
+/// and the last token of the pointer, or `None` for the root.
+fn resolve_pointer<'a>(
+    value: &'a Value,
+    pointer: &str,
+) -> Result<Option<(Path, &'a Value, String)>, JsonPatchError> {
+    if pointer.is_empty() {
+        return Ok(None);
+    }
+    let mut tokens = pointer
+        .strip_prefix('/')
+        .ok_or_else(|| JsonPatchError::InvalidPointer {
+            pointer: pointer.to_owned(),
+        })?
+        .split('/')
+        .map(|token| token.replace("~1", "/").replace("~0", "~"))
+        .collect::<Vec<_>>();
+    let last = tokens.pop().unwrap_or_default();
+    let mut path = Path::root();
+    let mut parent = value;
+    for token in tokens {
+        parent = match parent {
+            Value::List(vec) => {
+                let index = parse_index(&token, pointer)?;
+                path = path.index(index.try_into().unwrap());
+                vec.get(index)
+            }
+            Value::Map(map) | Value::Table(map) => {
+                let child = map.get(token.as_str());
+                path = path.key(token);
+                child
+            }
+            Value::Text(_) => return Err(JsonPatchError::Text { path }),
+            Value::Primitive(_) => None,
+        }
+        .ok_or_else(|| no_such_path(pointer))?;
+    }
+    match parent {
+        Value::Text(_) => Err(JsonPatchError::Text { path }),
+        Value::Primitive(_) => Err(no_such_path(pointer)),
+        _ => Ok(Some((path, parent, last))),
+    }
+}
+
+/// Parse an array index, which can't have leading zeros.
+fn parse_index(token: &str, pointer: &str) -> Result<usize, JsonPatchError> {
+    let valid = !token.is_empty()
+        && token.bytes().all(|b| b.is_ascii_digit())
+        && (token == "0" || !token.starts_with('0'));
+    let index = if valid { token.parse().ok() } else { None };
+    index.ok_or_else(|| JsonPatchError::InvalidPointer {
+        pointer: pointer.to_owned(),
+    })
+}
+
+fn no_such_path(pointer: &str) -> JsonPatchError {
+    JsonPatchError::NoSuchPath {
+        pointer: pointer.to_owned(),
+    }
+}
+
+/// Format the path as a JSON Pointer.
+fn to_pointer(elements: &[PathElement]) -> String {
+    let mut pointer = String::new();
+    for element in elements {
+        pointer.push('/');
+        match element {
+            PathElement::Key(k) => pointer.push_str(&k.replace('~', "~0").replace('/', "~1")),
+            PathElement::Index(i) => pointer.push_str(&i.to_string()),
+        }
+    }
+    pointer
+}
+
+/// Convert the value at the path to JSON.
+fn to_json(value: &Value, path: &Path) -> Result<serde_json::Value, JsonPatchError> {
+    Ok(match value {
+        Value::Map(map) | Value::Table(map) => serde_json::Value::Object(
+            map.iter()
+                .map(|(k, v)| Ok((k.to_string(), to_json(v, &path.clone().key(k.clone()))?)))
+                .collect::<Result<_, JsonPatchError>>()?,
+        ),
+        Value::List(vec) => serde_json::Value::Array(
+            vec.iter()
+                .enumerate()
+                .map(|(i, v)| to_json(v, &path.clone().index(i.try_into().unwrap())))
+                .collect::<Result<_, _>>()?,
+        ),
+        Value::Text(_) => return Err(JsonPatchError::Text { path: path.clone() }),
+        Value::Primitive(Primitive::Counter(_)) => {
+            return Err(JsonPatchError::Counter { path: path.clone() })
+        }
+        Value::Primitive(Primitive::Str(s)) => serde_json::Value::String(s.to_string()),
+        Value::Primitive(Primitive::Int(i)) | Value::Primitive(Primitive::Timestamp(i)) => {
+            serde_json::Value::from(*i)
+        }
+        Value::Primitive(Primitive::Uint(u)) => serde_json::Value::from(*u),
+        Value::Primitive(Primitive::F64(f)) => serde_json::Number::from_f64(*f)
+            .map(serde_json::Value::Number)
+            .ok_or_else(|| JsonPatchError::UnsupportedValue { path: path.clone() })?,
+        Value::Primitive(Primitive::Boolean(b)) => serde_json::Value::Bool(*b),
+        Value::Primitive(Primitive::Null) => serde_json::Value::Null,
+        Value::Primitive(_) => return Err(JsonPatchError::UnsupportedValue { path: path.clone() }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{apply_changes, diff_values, FromAutomerge};
+
+    #[test]
+    fn export_diff() {
+        let old = json!({"a": 1, "list": [1, 2, 3], "a/b": {"c": true}});
+        let new = json!({"list": [1, 3, 4], "a/b": {"c": false}, "d": "x"});
+        let changes = diff_values(&new.to_automerge(), &old.to_automerge()).unwrap();
+
+        let patch = to_json_patch(&changes, &old.to_automerge()).unwrap();
+        let mut patched = old;
+        ::json_patch::patch(&mut patched, &patch).unwrap();
+        assert_eq!(patched, new);
+        assert!(patch.0.iter().any(|op| matches!(
+            op,
+            PatchOperation::Add(AddOperation { path, .. }) if path == "/a~1b/c"
+        )));
+    }
+
+    #[test]
+    fn export_root_type_change() {
+        let old = json!([1, 2]);
+        let new = json!({"a": 1});
+        let changes = diff_values(&new.to_automerge(), &old.to_automerge()).unwrap();
+
+        let patch = to_json_patch(&changes, &old.to_automerge()).unwrap();
+        let mut patched = old;
+        ::json_patch::patch(&mut patched, &patch).unwrap();
+        assert_eq!(patched, new);
+    }
+
+    #[test]
+    fn import_patch() {
+        let old = json!({"a": {"0": 1}, "list": [1, 2]});
+        let patch = ::json_patch::from_value(json!([
+            {"op": "add", "path": "/list/-", "value": 3},
+            {"op": "add", "path": "/a/0", "value": 2},
+            {"op": "move", "from": "/list/0", "path": "/b"},
+            {"op": "copy", "from": "/a", "path": "/list/0"},
+            {"op": "test", "path": "/b", "value": 1},
+            {"op": "replace", "path": "/list/1", "value": null},
+            {"op": "remove", "path": "/a"},
+        ]))
+        .unwrap();
+
+        let mut expected = old.clone();
+        ::json_patch::patch(&mut expected, &patch).unwrap();
+        let mut value = old.to_automerge();
+        let changes = from_json_patch(&patch, &value).unwrap();
+        apply_changes(&mut value, &changes).unwrap();
+        assert_eq!(serde_json::Value::from_automerge(&value).unwrap(), expected);
+    }
+
+    #[test]
+    fn counters_and_text_are_errors() {
+        let mut map = HashMap::new();
+        map.insert("c".into(), Value::Primitive(Primitive::Counter(1)));
+        map.insert("t".into(), Value::Text(vec!["a".into()]));
+        let old = Value::Map(map);
+
+        assert!(matches!(
+            to_json_patch(&[LocalChange::increment(Path::root().key("c"))], &old),
+            Err(JsonPatchError::Counter { path }) if path == Path::root().key("c")
+        ));
+        assert!(matches!(
+            to_json_patch(&[LocalChange::delete(Path::root().key("t").index(0))], &old),
+            Err(JsonPatchError::Text { path }) if path == Path::root().key("t")
+        ));
+        let counters = Value::List(vec![Value::Primitive(Primitive::Counter(1))]);
+        assert!(matches!(
+            to_json_patch(&[LocalChange::set(Path::root().key("x"), counters)], &old),
+            Err(JsonPatchError::Counter { path }) if path == Path::root().key("x").index(0)
+        ));
+
+        let patch = ::json_patch::from_value(json!([
+            {"op": "replace", "path": "/c", "value": 2},
+        ]))
+        .unwrap();
+        assert!(matches!(
+            from_json_patch(&patch, &old),
+            Err(JsonPatchError::Counter { path }) if path == Path::root().key("c")
+        ));
+        // adding over a counter replaces it too
+        let patch = ::json_patch::from_value(json!([
+            {"op": "add", "path": "/c", "value": 2},
+        ]))
+        .unwrap();
+        assert!(matches!(
+            from_json_patch(&patch, &old),
+            Err(JsonPatchError::Counter { path }) if path == Path::root().key("c")
+        ));
+        let patch = ::json_patch::from_value(json!([
+            {"op": "copy", "from": "/t", "path": "/c"},
+        ]))
+        .unwrap();
+        assert!(matches!(
+            from_json_patch(&patch, &old),
+            Err(JsonPatchError::Counter { path }) if path == Path::root().key("c")
+        ));
+        let patch = ::json_patch::from_value(json!([
+            {"op": "add", "path": "/t/0", "value": "b"},
+        ]))
+        .unwrap();
+        assert!(matches!(
+            from_json_patch(&patch, &old),
+            Err(JsonPatchError::Text { path }) if path == Path::root().key("t")
+        ));
+    }
+}
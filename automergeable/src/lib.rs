@@ -101,18 +101,46 @@
 //! # }
 //! ```
 
-mod diff;
+mod apply;
+pub mod diff;
 mod document;
+#[cfg(feature = "json")]
+mod json;
+mod local;
+mod merge;
+mod migrate;
+mod subscribe;
+mod undo;
 
+pub use apply::{apply_change, apply_changes, ApplyChangeError};
 #[doc(hidden)]
-pub use automergeable_traits::Text;
-pub use automergeable_traits::{Automergeable, FromAutomerge, FromAutomergeError, ToAutomerge};
-pub use diff::{diff_values, diff_with_path};
-pub use document::{Document, DocumentChangeError};
+pub use automergeable_traits::{map_to_table, table_to_map, Text};
+pub use automergeable_traits::{
+    migrate, Automergeable, ChangedValues, Cursor, Diff, FromAutomerge, FromAutomergeError,
+    KeyPathElement, ListKeys, MigrationError, Table, ToAutomerge, VERSION_KEY,
+};
+pub use diff::{
+    diff_iter, diff_iter_with, diff_report, diff_values, diff_values_with, diff_values_with_keys,
+    diff_values_with_moves, diff_with_path, diff_with_path_and_options, invert, ColoredDiffReport,
+    CounterPolicy, DiffError, DiffIter, DiffKind, DiffNode, DiffOptions, DiffReport, Move,
+    PathFilter, TypeChangePolicy,
+};
+#[cfg(feature = "json")]
+pub use document::ApplyJsonPatchError;
+pub use document::{
+    ApplyPatchError, ConflictingValue, ConflictsError, Cursors, Document, DocumentChangeError,
+    LoadDocumentError, NewDocumentError, TypedChangeEvent,
+};
+#[cfg(feature = "json")]
+pub use json::{from_json_patch, to_json_patch, JsonPatchError};
+pub use local::{HistoryEntry, LocalDocument, LocalDocumentError};
+pub use merge::{merge3, merge3_values, Conflict, Merge};
+pub use subscribe::SubscriptionId;
+pub use undo::UndoManager;
 
 /// Derive macro magic
 extern crate automergeable_derive;
-pub use automergeable_derive::{Automergeable, FromAutomerge, ToAutomerge};
+pub use automergeable_derive::{Automergeable, Diff, FromAutomerge, ToAutomerge};
 
 /// needed for derive macro inner workings
 #[doc(hidden)]
@@ -0,0 +1,440 @@
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+};
+
+use automerge::{Backend, Change, ChangeHash, Frontend, InvalidPatch, Path, Value};
+use automerge_protocol::{ActorId, Patch};
+
+use crate::{
+    ApplyPatchError, Automergeable, Document, DocumentChangeError, FromAutomergeError,
+    LoadDocumentError, NewDocumentError, TypedChangeEvent,
+};
+
+/// An error type for operations on local documents.
+#[derive(Debug, thiserror::Error)]
+pub enum LocalDocumentError<E: Error = std::convert::Infallible> {
+    /// A failure to change the document.
+    #[error(transparent)]
+    ChangeError(#[from] DocumentChangeError<E>),
+    /// A failure to create the document.
+    #[error(transparent)]
+    NewDocumentError(#[from] NewDocumentError<InvalidPatch>),
+    /// A failure from the backend, such as applying invalid changes.
+    #[error(transparent)]
+    BackendError(#[from] automerge::AutomergeError),
+    /// A failure to apply the backend's patch to the document.
+    #[error(transparent)]
+    ApplyPatchError(#[from] ApplyPatchError<InvalidPatch>),
+    /// A failure to convert a past value to a typed value.
+    #[error(transparent)]
+    FromError(#[from] FromAutomergeError),
+    /// A failure to reload the document from the backend after a local change failed.
+    #[error(transparent)]
+    LoadDocumentError(#[from] LoadDocumentError),
+}
+
+/// The value of a document after one of the changes in its history.
+#[derive(Debug)]
+pub struct HistoryEntry<T> {
+    /// The hash of the change.
+    pub hash: ChangeHash,
+    /// The actor which made the change.
+    pub actor_id: ActorId,
+    /// The sequence number of the change for its actor.
+    pub seq: u64,
+    /// The time the change was made.
+    pub time: i64,
+    /// The message of the change.
+    pub message: Option<String>,
+    /// The value of the document after the change, or the error from converting it.
+    pub value: Result<T, FromAutomergeError>,
+}
+
+/// A typed document which owns both the frontend and the backend.
+///
+/// Local changes are applied to the backend straight away and the resulting patch fed back to the
+/// frontend, so the typed value is always current.
+#[derive(Debug)]
+pub struct LocalDocument<T>
+where
+    T: Automergeable,
+{
+    document: Document<T, Frontend>,
+    backend: Backend,
+}
+
+impl<T> LocalDocument<T>
+where
+    T: Automergeable + Clone,
+{
+    /// Construct a new, empty document.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the empty document can't be converted to `T`, see [`Document::new`].
+    pub fn new() -> Self {
+        Self {
+            document: Document::new(Frontend::new()),
+            backend: Backend::new(),
+        }
+    }
+
+    /// Construct a new document with an initial value.
+    pub fn with_initial(value: T) -> Result<Self, LocalDocumentError> {
+        let (document, change) = Document::with_initial(Frontend::new(), value)?;
+        let mut local = Self {
+            document,
+            backend: Backend::new(),
+        };
+        local.apply_local_change(change)?;
+        Ok(local)
+    }
+
+    /// Load a document from the bytes of a saved backend.
+    pub fn load(bytes: Vec<u8>) -> Result<Self, LoadDocumentError> {
+        let (document, backend) = Document::load(bytes)?;
+        Ok(Self { document, backend })
+    }
+
+    /// Load a document from a list of changes.
+    pub fn from_changes(changes: Vec<Change>) -> Result<Self, LoadDocumentError> {
+        let (document, backend) = Document::from_changes(changes)?;
+        Ok(Self { document, backend })
+    }
+
+    /// Get the current value of the document.
+    pub fn get(&self) -> &T {
+        self.document.get()
+    }
+
+    /// Perform a change on the document, applying it to the backend.
+    ///
+    /// The change is returned so that it can be sent to other peers. If the backend rejects the
+    /// change then the document is reloaded from the backend, with a new actor id, so that it is
+    /// left as it was before the change. If the backend accepts the change but its patch can't be
+    /// applied then the document is reloaded with the change, again with a new actor id, and the
+    /// error is returned. The change can then be found with [`LocalDocument::get_changes`].
+    pub fn change<C, O, E>(
+        &mut self,
+        change: C,
+    ) -> Result<(O, Option<Change>), LocalDocumentError<E>>
+    where
+        E: Error,
+        C: FnOnce(&mut T) -> Result<O, E>,
+    {
+        let (res, change) = self.document.change(change)?;
+        Ok((res, self.apply_local_change(change)?))
+    }
+
+    /// Perform a change on the document with a message, applying it to the backend.
+    pub fn change_with_message<C, O, E>(
+        &mut self,
+        message: String,
+        change: C,
+    ) -> Result<(O, Option<Change>), LocalDocumentError<E>>
+    where
+        E: Error,
+        C: FnOnce(&mut T) -> Result<O, E>,
+    {
+        let (res, change) = self.document.change_with_message(message, change)?;
+        Ok((res, self.apply_local_change(change)?))
+    }
+
+    /// Apply a change which the document has made to the backend, keeping the two in step.
+    fn apply_local_change<E: Error>(
+        &mut self,
+        change: Option<automerge_protocol::Change>,
+    ) -> Result<Option<Change>, LocalDocumentError<E>> {
+        let change = match change {
+            Some(change) => change,
+            None => return Ok(None),
+        };
+        let (patch, change) = match self.backend.apply_local_change(change) {
+            Ok(applied) => applied,
+            Err(e) => {
+                // the frontend already has the change so roll it back
+                self.document.reload(&self.backend)?;
+                return Err(e.into());
+            }
+        };
+        if let Err(e) = self.document.apply_patch(patch) {
+            // the backend has the change so catch the document up with it
+            self.document.reload(&self.backend)?;
+            return Err(e.into());
+        }
+        Ok(Some(change))
+    }
+
+    /// Apply changes from other peers, updating the value.
+    ///
+    /// Returns the changes which they made to the value. If the changed value can't be converted
+    /// to `T` then the backend is rolled back to before the changes and the document reloaded from
+    /// it, with a new actor id, so that it is left as it was.
+    pub fn apply_changes(
+        &mut self,
+        changes: Vec<Change>,
+    ) -> Result<Vec<TypedChangeEvent>, LocalDocumentError> {
+        let hashes = changes.iter().map(|c| c.hash).collect::<HashSet<_>>();
+        let patch = self.backend.apply_changes(changes)?;
+        match self.document.apply_patch(patch) {
+            Ok(events) => Ok(events),
+            Err(e) => {
+                let mut backend = Backend::new();
+                backend.apply_changes(
+                    self.backend
+                        .get_changes(&[])
+                        .into_iter()
+                        .filter(|c| !hashes.contains(&c.hash))
+                        .cloned()
+                        .collect(),
+                )?;
+                self.backend = backend;
+                self.document.reload(&self.backend)?;
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Get the changes which aren't ancestors of the `since` hashes, such as those another peer
+    /// doesn't have yet.
+    pub fn get_changes(&self, since: &[ChangeHash]) -> Vec<&Change> {
+        self.backend.get_changes(since)
+    }
+
+    /// Get the hashes of the latest changes.
+    pub fn get_heads(&self) -> Vec<ChangeHash> {
+        self.backend.get_heads()
+    }
+
+    /// Get the value of the document after each change, in the order the backend applied them.
+    ///
+    /// Each value includes every earlier change in that order, including concurrent ones. Use
+    /// [`LocalDocument::value_at`] for just a change and its dependencies.
+    pub fn history(&self) -> Result<Vec<HistoryEntry<T>>, LocalDocumentError> {
+        let mut backend = Backend::new();
+        let mut frontend = Frontend::new();
+        let mut history = Vec::new();
+        for change in self.backend.get_changes(&[]) {
+            let patch = backend.apply_changes(vec![change.clone()])?;
+            let value = replay_patch(&mut frontend, patch)?;
+            history.push(HistoryEntry {
+                hash: change.hash,
+                actor_id: change.actor_id().clone(),
+                seq: change.seq,
+                time: change.time,
+                message: change.message(),
+                value: T::from_automerge(&value),
+            })
+        }
+        Ok(history)
+    }
+
+    /// Get the value of the document with just the change with the hash and its dependencies.
+    ///
+    /// Returns `Ok(None)` if there is no change with the hash.
+    pub fn value_at(&self, hash: &ChangeHash) -> Result<Option<T>, LocalDocumentError> {
+        let changes = self.backend.get_changes(&[]);
+        let by_hash = changes
+            .iter()
+            .map(|c| (c.hash, *c))
+            .collect::<HashMap<_, _>>();
+        let mut wanted = HashSet::new();
+        let mut stack = vec![*hash];
+        while let Some(hash) = stack.pop() {
+            if wanted.insert(hash) {
+                match by_hash.get(&hash) {
+                    Some(change) => stack.extend(change.deps.iter().copied()),
+                    None => return Ok(None),
+                }
+            }
+        }
+        let mut backend = Backend::new();
+        // the backend returns changes with their dependencies first
+        let patch = backend.apply_changes(
+            changes
+                .into_iter()
+                .filter(|c| wanted.contains(&c.hash))
+                .cloned()
+                .collect(),
+        )?;
+        let value = replay_patch(&mut Frontend::new(), patch)?;
+        Ok(Some(T::from_automerge(&value)?))
+    }
+
+    /// Get the value of the document with just the change with the actor and sequence number and
+    /// its dependencies.
+    ///
+    /// Returns `Ok(None)` if there is no such change.
+    pub fn value_at_seq(
+        &self,
+        actor_id: &ActorId,
+        seq: u64,
+    ) -> Result<Option<T>, LocalDocumentError> {
+        let hash = self
+            .backend
+            .get_changes(&[])
+            .into_iter()
+            .find(|c| c.actor_id() == actor_id && c.seq == seq)
+            .map(|c| c.hash);
+        hash.map_or(Ok(None), |hash| self.value_at(&hash))
+    }
+
+    /// Save the document to bytes, which can be loaded with [`LocalDocument::load`].
+    pub fn save(&self) -> Result<Vec<u8>, automerge::AutomergeError> {
+        self.backend.save()
+    }
+}
+
+/// Apply a patch to the frontend, returning the new root value.
+fn replay_patch(frontend: &mut Frontend, patch: Patch) -> Result<Value, LocalDocumentError> {
+    frontend
+        .apply_patch(patch)
+        .map_err(ApplyPatchError::FrontendError)?;
+    Ok(frontend
+        .get_value(&Path::root())
+        .unwrap_or_else(|| Value::Map(HashMap::new())))
+}
+
+impl<T> Default for LocalDocument<T>
+where
+    T: Automergeable + Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn local_changes_and_sync() {
+        let mut ours = LocalDocument::<HashMap<String, u64>>::new();
+        let ((), change) = ours
+            .change::<_, _, automerge::InvalidChangeRequest>(|map| {
+                map.insert("a".to_owned(), 1);
+                Ok(())
+            })
+            .unwrap();
+        assert!(change.is_some());
+        assert_eq!(ours.get().get("a"), Some(&1));
+        assert_eq!(ours.get_changes(&[]).len(), 1);
+
+        let mut theirs = LocalDocument::<HashMap<String, u64>>::new();
+        let events = theirs
+            .apply_changes(ours.get_changes(&[]).into_iter().cloned().collect())
+            .unwrap();
+        assert_eq!(
+            events,
+            vec![TypedChangeEvent::Inserted {
+                path: automerge::Path::root().key("a"),
+                value: automerge::Value::Primitive(automerge::Primitive::Uint(1)),
+            }]
+        );
+        assert_eq!(theirs.get(), ours.get());
+        assert_eq!(theirs.get_heads(), ours.get_heads());
+
+        let heads = ours.get_heads();
+        ours.change::<_, _, automerge::InvalidChangeRequest>(|map| {
+            map.insert("b".to_owned(), 2);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(ours.get_changes(&heads).len(), 1);
+
+        let loaded = LocalDocument::<HashMap<String, u64>>::load(ours.save().unwrap()).unwrap();
+        assert_eq!(loaded.get(), ours.get());
+    }
+
+    #[test]
+    fn unconvertible_changes_are_rolled_back() {
+        let mut ours = LocalDocument::<HashMap<String, u64>>::new();
+        ours.change::<_, _, automerge::InvalidChangeRequest>(|map| {
+            map.insert("a".to_owned(), 1);
+            Ok(())
+        })
+        .unwrap();
+
+        let mut theirs = LocalDocument::<HashMap<String, String>>::new();
+        let ((), change) = theirs
+            .change::<_, _, automerge::InvalidChangeRequest>(|map| {
+                map.insert("b".to_owned(), "b".to_owned());
+                Ok(())
+            })
+            .unwrap();
+        assert!(ours.apply_changes(vec![change.unwrap()]).is_err());
+        assert_eq!(ours.get().len(), 1);
+        assert_eq!(ours.get_changes(&[]).len(), 1);
+
+        // the document still matches the backend so later changes diff against the right value
+        ours.change::<_, _, automerge::InvalidChangeRequest>(|map| {
+            map.insert("a".to_owned(), 2);
+            Ok(())
+        })
+        .unwrap();
+        let loaded = LocalDocument::<HashMap<String, u64>>::load(ours.save().unwrap()).unwrap();
+        assert_eq!(loaded.get(), ours.get());
+    }
+
+    #[test]
+    fn history_and_value_at() {
+        let mut doc = LocalDocument::<HashMap<String, u64>>::new();
+        for i in 0..3 {
+            doc.change_with_message::<_, _, automerge::InvalidChangeRequest>(
+                format!("change {}", i),
+                |map| {
+                    map.insert(i.to_string(), i);
+                    Ok(())
+                },
+            )
+            .unwrap();
+        }
+
+        let history = doc.history().unwrap();
+        assert_eq!(history.len(), 3);
+        for (i, entry) in history.iter().enumerate() {
+            assert_eq!(entry.message, Some(format!("change {}", i)));
+            assert_eq!(entry.value.as_ref().unwrap().len(), i + 1);
+            assert_eq!(
+                doc.value_at(&entry.hash).unwrap().as_ref(),
+                entry.value.as_ref().ok()
+            );
+            assert_eq!(
+                doc.value_at_seq(&entry.actor_id, entry.seq)
+                    .unwrap()
+                    .as_ref(),
+                entry.value.as_ref().ok()
+            );
+        }
+        assert_eq!(history.last().unwrap().value.as_ref().unwrap(), doc.get());
+
+        // decoding errors are reported per entry
+        let mut untyped = LocalDocument::<Value>::new();
+        for value in &[
+            Value::Primitive(automerge::Primitive::Str("a".into())),
+            Value::Primitive(automerge::Primitive::Uint(1)),
+        ] {
+            untyped
+                .change::<_, _, automerge::InvalidChangeRequest>(|root| {
+                    *root = Value::Map(std::iter::once(("a".into(), value.clone())).collect());
+                    Ok(())
+                })
+                .unwrap();
+        }
+        let typed = LocalDocument::<HashMap<String, u64>>::from_changes(
+            untyped.get_changes(&[]).into_iter().cloned().collect(),
+        )
+        .unwrap();
+        let history = typed.history().unwrap();
+        assert!(history[0].value.is_err());
+        assert_eq!(history[1].value.as_ref().unwrap().get("a"), Some(&1));
+        assert!(matches!(
+            typed.value_at(&history[0].hash),
+            Err(LocalDocumentError::FromError(_))
+        ));
+    }
+}
@@ -0,0 +1,534 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    convert::TryInto,
+};
+
+use automerge::{InvalidChangeRequest, LocalChange, Path, Primitive, Value};
+
+use crate::{
+    diff::{sequence, DiffError},
+    diff_with_path, ToAutomerge,
+};
+
+/// A path which both sides changed differently.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    /// The path to the conflicting value.
+    pub path: Path,
+    /// The value before either change, if there was one.
+    pub base: Option<Value>,
+    /// Our value, if there is one.
+    pub ours: Option<Value>,
+    /// Their value, if there is one.
+    pub theirs: Option<Value>,
+}
+
+/// The result of a three way merge.
+#[derive(Debug, Clone)]
+pub struct Merge {
+    /// The changes to apply on top of their value.
+    pub changes: Vec<LocalChange>,
+    /// The paths which both sides changed differently, for which our value is used.
+    ///
+    /// Their value is kept instead where it is a counter, which can't be overwritten.
+    pub conflicts: Vec<Conflict>,
+}
+
+/// Merge the changes from `base` to `ours` on top of the changes from `base` to `theirs`.
+///
+/// Changes to different parts of the value are combined, recursing into maps and elements of lists
+/// which both sides changed in place, and merging the changed regions of lists and text, keeping
+/// both sides' insertions at the same place. Counters which both sides incremented are incremented
+/// by our delta. Where both sides changed something differently our value wins and a [`Conflict`]
+/// is recorded.
+pub fn merge3<T: ToAutomerge>(base: &T, ours: &T, theirs: &T) -> Result<Merge, DiffError> {
+    merge3_values(
+        &base.to_automerge(),
+        &ours.to_automerge(),
+        &theirs.to_automerge(),
+    )
+}
+
+/// Merge the changes from `base` to `ours` on top of the changes from `base` to `theirs`.
+///
+/// See [`merge3`] for details.
+pub fn merge3_values(base: &Value, ours: &Value, theirs: &Value) -> Result<Merge, DiffError> {
+    let mut merge = Merge {
+        changes: Vec::new(),
+        conflicts: Vec::new(),
+    };
+    merge_values(
+        Some(base),
+        Some(ours),
+        Some(theirs),
+        Path::root(),
+        &mut merge,
+    )?;
+    Ok(merge)
+}
+
+fn merge_values(
+    base: Option<&Value>,
+    ours: Option<&Value>,
+    theirs: Option<&Value>,
+    path: Path,
+    merge: &mut Merge,
+) -> Result<(), DiffError> {
+    if ours == base || ours == theirs {
+        // nothing of ours to apply
+        return Ok(());
+    }
+    if theirs == base {
+        // only we changed it
+        replace_theirs(ours, base, ours, theirs, path, false, merge)?;
+        return Ok(());
+    }
+    match (base, ours, theirs) {
+        (_, Some(Value::Map(ours_map)), Some(Value::Map(theirs_map)))
+        | (_, Some(Value::Table(ours_map)), Some(Value::Table(theirs_map))) => {
+            let empty = HashMap::new();
+            let base_map = match base {
+                Some(Value::Map(map)) | Some(Value::Table(map)) => map,
+                _ => &empty,
+            };
+            let keys = base_map
+                .keys()
+                .chain(ours_map.keys())
+                .chain(theirs_map.keys())
+                .collect::<BTreeSet<_>>();
+            for k in keys {
+                merge_values(
+                    base_map.get(k),
+                    ours_map.get(k),
+                    theirs_map.get(k),
+                    path.clone().key(k.clone()),
+                    merge,
+                )?
+            }
+        }
+        (
+            Some(Value::List(base_vec)),
+            Some(Value::List(ours_vec)),
+            Some(Value::List(theirs_vec)),
+        ) => {
+            let (merged, conflicted, pairs) = merge_sequences(base_vec, ours_vec, theirs_vec, true);
+            let merged = Value::List(merged);
+            if replace_theirs(
+                Some(&merged),
+                base,
+                ours,
+                theirs,
+                path.clone(),
+                conflicted,
+                merge,
+            )? {
+                // the merged list has their elements where both sides changed them in place
+                for pair in pairs {
+                    merge_values(
+                        Some(&base_vec[pair.base]),
+                        Some(&ours_vec[pair.ours]),
+                        Some(&theirs_vec[pair.theirs]),
+                        path.clone().index(pair.merged.try_into().unwrap()),
+                        merge,
+                    )?
+                }
+            }
+        }
+        (
+            Some(Value::Text(base_vec)),
+            Some(Value::Text(ours_vec)),
+            Some(Value::Text(theirs_vec)),
+        ) => {
+            let (merged, conflicted, _) = merge_sequences(base_vec, ours_vec, theirs_vec, false);
+            let merged = Value::Text(merged);
+            replace_theirs(Some(&merged), base, ours, theirs, path, conflicted, merge)?;
+        }
+        (
+            Some(Value::Primitive(Primitive::Counter(base_int))),
+            Some(Value::Primitive(Primitive::Counter(ours_int))),
+            Some(Value::Primitive(Primitive::Counter(_))),
+        ) => {
+            // increments commute so apply ours on top of theirs
+            match ours_int.checked_sub(*base_int) {
+                Some(delta) => merge.changes.push(LocalChange::increment_by(path, delta)),
+                None => merge.conflicts.push(conflict(base, ours, theirs, path)),
+            }
+        }
+        _ => {
+            replace_theirs(ours, base, ours, theirs, path, true, merge)?;
+        }
+    }
+    Ok(())
+}
+
+fn conflict(
+    base: Option<&Value>,
+    ours: Option<&Value>,
+    theirs: Option<&Value>,
+    path: Path,
+) -> Conflict {
+    Conflict {
+        path,
+        base: base.cloned(),
+        ours: ours.cloned(),
+        theirs: theirs.cloned(),
+    }
+}
+
+/// Record the changes to replace their value with `new`, and a conflict if `conflicted`.
+///
+/// If their value is, or contains, a counter which `new` would overwrite then theirs is kept and a
+/// conflict recorded. Returns whether `new` was used.
+fn replace_theirs(
+    new: Option<&Value>,
+    base: Option<&Value>,
+    ours: Option<&Value>,
+    theirs: Option<&Value>,
+    path: Path,
+    conflicted: bool,
+    merge: &mut Merge,
+) -> Result<bool, DiffError> {
+    if overwrites_counter(new, theirs) {
+        merge.conflicts.push(conflict(base, ours, theirs, path));
+        return Ok(false);
+    }
+    match diff_with_path(new, theirs, path.clone()) {
+        Ok(mut changes) => {
+            if conflicted {
+                merge.conflicts.push(conflict(base, ours, theirs, path))
+            }
+            merge.changes.append(&mut changes);
+            Ok(true)
+        }
+        Err(InvalidChangeRequest::CannotOverwriteCounter { .. }) => {
+            merge.conflicts.push(conflict(base, ours, theirs, path));
+            Ok(false)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Whether `new` would replace a counter in their value with another type.
+fn overwrites_counter(new: Option<&Value>, theirs: Option<&Value>) -> bool {
+    match (new, theirs) {
+        (Some(Value::Primitive(Primitive::Counter(_))), _) => false,
+        (Some(_), Some(Value::Primitive(Primitive::Counter(_)))) => true,
+        (Some(Value::Map(new)), Some(Value::Map(theirs)))
+        | (Some(Value::Table(new)), Some(Value::Table(theirs))) => theirs
+            .iter()
+            .any(|(k, theirs)| overwrites_counter(new.get(k), Some(theirs))),
+        _ => false,
+    }
+}
+
+/// The indices of an element which both sides changed in place.
+struct Pair {
+    merged: usize,
+    base: usize,
+    ours: usize,
+    theirs: usize,
+}
+
+/// Merge the changes both sides made to a sequence, returning the merged sequence and whether
+/// any of the changes overlapped.
+///
+/// Overlapping changes take our elements, unless `pair` is set and both sides changed the same
+/// elements in place. Their elements are used for those, along with the [`Pair`]s to merge.
+/// Insertions by both sides at the same place don't overlap, and both are kept with ours first.
+fn merge_sequences<T: Clone + PartialEq>(
+    base: &[T],
+    ours: &[T],
+    theirs: &[T],
+    pair: bool,
+) -> (Vec<T>, bool, Vec<Pair>) {
+    let hunks = |side: &[T]| {
+        sequence::diff(base, side, PartialEq::eq)
+            .into_iter()
+            .filter_map(|op| match op {
+                sequence::Op::Replace { old, new } => Some((old, new)),
+                sequence::Op::Equal { .. } => None,
+            })
+            .collect::<Vec<_>>()
+    };
+    let ours_hunks = hunks(ours);
+    let theirs_hunks = hunks(theirs);
+
+    let mut merged = Vec::new();
+    let mut conflicted = false;
+    let mut pairs = Vec::new();
+    // the next hunk from each side
+    let (mut o, mut t) = (0, 0);
+    // the difference between positions in each side and the base after the hunks so far
+    let (mut ours_delta, mut theirs_delta) = (0_isize, 0_isize);
+    let mut base_index = 0;
+    loop {
+        let start = match (ours_hunks.get(o), theirs_hunks.get(t)) {
+            (None, None) => break,
+            (Some((old, _)), None) | (None, Some((old, _))) => old.start,
+            (Some((ours_old, _)), Some((theirs_old, _))) => ours_old.start.min(theirs_old.start),
+        };
+        merged.extend_from_slice(&base[base_index..start]);
+
+        // group together the hunks which overlap, starting with any insertions at the start as
+        // they come before the other side's changes there rather than overlapping them
+        let (first_o, first_t) = (o, t);
+        let mut end = start;
+        let overlaps = |old: &std::ops::Range<usize>, end: usize| {
+            if end == start {
+                old.start == start && old.is_empty()
+            } else if old.is_empty() {
+                start < old.start && old.start < end
+            } else {
+                old.start < end
+            }
+        };
+        loop {
+            if let Some((old, _)) = ours_hunks.get(o).filter(|(old, _)| overlaps(old, end)) {
+                end = end.max(old.end);
+                o += 1;
+            } else if let Some((old, _)) = theirs_hunks.get(t).filter(|(old, _)| overlaps(old, end))
+            {
+                end = end.max(old.end);
+                t += 1;
+            } else if o == first_o && t == first_t {
+                // no insertions at the start so take the first change there
+                match ours_hunks.get(o).filter(|(old, _)| old.start == start) {
+                    Some((old, _)) => {
+                        end = old.end;
+                        o += 1;
+                    }
+                    None => {
+                        end = theirs_hunks[t].0.end;
+                        t += 1;
+                    }
+                }
+            } else {
+                break;
+            }
+        }
+
+        let growth = |hunks: &[(std::ops::Range<usize>, std::ops::Range<usize>)]| {
+            hunks
+                .iter()
+                .map(|(old, new)| new.len() as isize - old.len() as isize)
+                .sum::<isize>()
+        };
+        let ours_after = ours_delta + growth(&ours_hunks[first_o..o]);
+        let theirs_after = theirs_delta + growth(&theirs_hunks[first_t..t]);
+        let ours_part = &ours[offset(start, ours_delta)..offset(end, ours_after)];
+        let theirs_part = &theirs[offset(start, theirs_delta)..offset(end, theirs_after)];
+        let both_inserted;
+        let part = if t == first_t {
+            ours_part
+        } else if o == first_o || ours_part == theirs_part {
+            theirs_part
+        } else if end == start {
+            // both sides inserted at the same place so keep both, ours first
+            both_inserted = [ours_part, theirs_part].concat();
+            &both_inserted
+        } else if pair && ours_part.len() == end - start && theirs_part.len() == end - start {
+            for i in 0..end - start {
+                pairs.push(Pair {
+                    merged: merged.len() + i,
+                    base: start + i,
+                    ours: offset(start, ours_delta) + i,
+                    theirs: offset(start, theirs_delta) + i,
+                })
+            }
+            theirs_part
+        } else {
+            conflicted = true;
+            ours_part
+        };
+        merged.extend_from_slice(part);
+
+        ours_delta = ours_after;
+        theirs_delta = theirs_after;
+        base_index = end;
+    }
+    merged.extend_from_slice(&base[base_index..]);
+    (merged, conflicted, pairs)
+}
+
+const fn offset(index: usize, delta: isize) -> usize {
+    (index as isize + delta) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn map(entries: Vec<(&str, Value)>) -> Value {
+        Value::Map(
+            entries
+                .into_iter()
+                .map(|(k, v)| (k.into(), v))
+                .collect::<HashMap<_, _>>(),
+        )
+    }
+
+    fn uint(u: u64) -> Value {
+        Value::Primitive(Primitive::Uint(u))
+    }
+
+    #[test]
+    fn merge_disjoint_changes() {
+        let base = map(vec![("a", uint(1)), ("b", uint(1))]);
+        let ours = map(vec![("a", uint(2)), ("b", uint(1))]);
+        let theirs = map(vec![("a", uint(1)), ("b", uint(3))]);
+
+        let merge = merge3_values(&base, &ours, &theirs).unwrap();
+        assert!(merge.conflicts.is_empty());
+        assert_eq!(
+            merge.changes,
+            vec![LocalChange::set(Path::root().key("a"), uint(2))]
+        );
+    }
+
+    #[test]
+    fn merge_conflicting_changes() {
+        let base = map(vec![("a", uint(1))]);
+        let ours = map(vec![("a", uint(2))]);
+        let theirs = map(vec![("a", uint(3))]);
+
+        let merge = merge3_values(&base, &ours, &theirs).unwrap();
+        assert_eq!(
+            merge.conflicts,
+            vec![Conflict {
+                path: Path::root().key("a"),
+                base: Some(uint(1)),
+                ours: Some(uint(2)),
+                theirs: Some(uint(3)),
+            }]
+        );
+        assert_eq!(
+            merge.changes,
+            vec![LocalChange::set(Path::root().key("a"), uint(2))]
+        );
+    }
+
+    #[test]
+    fn merge_counters() {
+        let counter = |i| Value::Primitive(Primitive::Counter(i));
+        let base = map(vec![("c", counter(1))]);
+        let ours = map(vec![("c", counter(3))]);
+        let theirs = map(vec![("c", counter(5))]);
+
+        let merge = merge3_values(&base, &ours, &theirs).unwrap();
+        assert!(merge.conflicts.is_empty());
+        assert_eq!(
+            merge.changes,
+            vec![LocalChange::increment_by(Path::root().key("c"), 2)]
+        );
+    }
+
+    #[test]
+    fn merge_list_inserts() {
+        let base = map(vec![("l", Value::List(vec![uint(1), uint(2), uint(3)]))]);
+        let ours = map(vec![(
+            "l",
+            Value::List(vec![uint(0), uint(1), uint(2), uint(3)]),
+        )]);
+        let theirs = map(vec![("l", Value::List(vec![uint(1), uint(2)]))]);
+
+        let merge = merge3_values(&base, &ours, &theirs).unwrap();
+        assert!(merge.conflicts.is_empty());
+        assert_eq!(
+            merge.changes,
+            vec![LocalChange::insert(Path::root().key("l").index(0), uint(0))]
+        );
+    }
+
+    #[test]
+    fn merge_concurrent_inserts() {
+        let base = map(vec![("l", Value::List(vec![uint(1), uint(2)]))]);
+        let ours = map(vec![("l", Value::List(vec![uint(1), uint(5), uint(2)]))]);
+        let theirs = map(vec![(
+            "l",
+            Value::List(vec![uint(1), uint(6), uint(7), uint(2)]),
+        )]);
+
+        // both insertions are kept, ours first
+        let merge = merge3_values(&base, &ours, &theirs).unwrap();
+        assert!(merge.conflicts.is_empty());
+        assert_eq!(
+            merge.changes,
+            vec![LocalChange::insert(Path::root().key("l").index(1), uint(5))]
+        );
+
+        // an insertion next to the other side's deletion doesn't overlap it either
+        let theirs = map(vec![("l", Value::List(vec![uint(1)]))]);
+        let merge = merge3_values(&base, &ours, &theirs).unwrap();
+        assert!(merge.conflicts.is_empty());
+        assert_eq!(
+            merge.changes,
+            vec![LocalChange::insert(Path::root().key("l").index(1), uint(5))]
+        );
+    }
+
+    #[test]
+    fn merge_list_element_fields() {
+        let element = |a, b| map(vec![("a", uint(a)), ("b", uint(b))]);
+        let base = map(vec![("l", Value::List(vec![uint(0), element(1, 1)]))]);
+        let ours = map(vec![("l", Value::List(vec![uint(0), element(2, 1)]))]);
+        let theirs = map(vec![(
+            "l",
+            Value::List(vec![uint(5), uint(0), element(1, 3)]),
+        )]);
+
+        let merge = merge3_values(&base, &ours, &theirs).unwrap();
+        assert!(merge.conflicts.is_empty());
+        assert_eq!(
+            merge.changes,
+            vec![LocalChange::set(
+                Path::root().key("l").index(2).key("a"),
+                uint(2)
+            )]
+        );
+    }
+
+    #[test]
+    fn merge_overwritten_counter() {
+        let base = map(vec![("c", uint(1))]);
+        let ours = map(vec![("c", uint(2))]);
+        let theirs = map(vec![("c", Value::Primitive(Primitive::Counter(1)))]);
+
+        let merge = merge3_values(&base, &ours, &theirs).unwrap();
+        // their counter can't be overwritten so is kept
+        assert!(merge.changes.is_empty());
+        assert_eq!(
+            merge.conflicts,
+            vec![Conflict {
+                path: Path::root().key("c"),
+                base: Some(uint(1)),
+                ours: Some(uint(2)),
+                theirs: Some(Value::Primitive(Primitive::Counter(1))),
+            }]
+        );
+    }
+
+    #[test]
+    fn merge_overlapping_text() {
+        let text = |s: &str| Value::Text(s.chars().map(|c| c.to_string().into()).collect());
+        let base = map(vec![("t", text("abc"))]);
+        let ours = map(vec![("t", text("axc"))]);
+        let theirs = map(vec![("t", text("ayc!"))]);
+
+        let merge = merge3_values(&base, &ours, &theirs).unwrap();
+        assert_eq!(merge.conflicts.len(), 1);
+        assert_eq!(merge.conflicts[0].path, Path::root().key("t"));
+        // ours wins the conflict but their addition is kept
+        assert_eq!(
+            merge.changes,
+            vec![
+                LocalChange::delete(Path::root().key("t").index(1)),
+                LocalChange::insert(
+                    Path::root().key("t").index(1),
+                    Value::Primitive(Primitive::Str("x".into()))
+                ),
+            ]
+        );
+    }
+}
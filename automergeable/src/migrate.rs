@@ -0,0 +1,32 @@
+use std::borrow::Cow;
+
+use automerge::Value;
+
+use crate::{migrate, FromAutomerge, MigrationError, VERSION_KEY};
+
+/// Migrate a value to the current schema version of `T`, borrowing it if it is already current.
+pub fn migrated<T>(value: &Value) -> Result<Cow<'_, Value>, MigrationError>
+where
+    T: FromAutomerge,
+{
+    Ok(migrate::<T>(value)?.map_or(Cow::Borrowed(value), Cow::Owned))
+}
+
+/// Whether `stored` is missing any of the version markers in `current`, the value as it would be
+/// written now, such as when a nested value is yet to be migrated.
+pub fn outdated(current: &Value, stored: &Value) -> bool {
+    match (current, stored) {
+        (Value::Map(current), Value::Map(stored))
+        | (Value::Table(current), Value::Table(stored)) => matches!(
+            current.get(VERSION_KEY),
+            Some(version) if stored.get(VERSION_KEY) != Some(version)
+        ) || current.iter().any(
+            |(key, current)| matches!(stored.get(key), Some(stored) if outdated(current, stored)),
+        ),
+        (Value::List(current), Value::List(stored)) => current
+            .iter()
+            .zip(stored)
+            .any(|(current, stored)| outdated(current, stored)),
+        _ => false,
+    }
+}
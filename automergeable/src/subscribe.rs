@@ -0,0 +1,56 @@
+use std::fmt::Debug;
+
+use automerge::{Path, Value};
+
+use crate::apply::resolve;
+
+/// An identifier for a subscription to a document, used to unsubscribe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+type Callback = Box<dyn FnMut(Option<&Value>, Option<&Value>) + Send>;
+
+/// Callbacks for changes to the values at paths.
+#[derive(Default)]
+pub struct Subscriptions {
+    next_id: u64,
+    subscriptions: Vec<(SubscriptionId, Path, Callback)>,
+}
+
+impl Subscriptions {
+    pub fn subscribe(&mut self, path: Path, callback: Callback) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id);
+        self.next_id += 1;
+        self.subscriptions.push((id, path, callback));
+        id
+    }
+
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let len = self.subscriptions.len();
+        self.subscriptions.retain(|(i, _, _)| *i != id);
+        self.subscriptions.len() != len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscriptions.is_empty()
+    }
+
+    /// Call the callbacks of the paths whose values differ between `old` and `new`.
+    pub fn notify(&mut self, old: &Value, new: &Value) {
+        for (_, path, callback) in &mut self.subscriptions {
+            let elements = path.clone().elements();
+            let (old, new) = (resolve(old, &elements), resolve(new, &elements));
+            if old != new {
+                callback(old, new)
+            }
+        }
+    }
+}
+
+impl Debug for Subscriptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map()
+            .entries(self.subscriptions.iter().map(|(id, path, _)| (id, path)))
+            .finish()
+    }
+}
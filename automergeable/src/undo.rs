@@ -0,0 +1,178 @@
+use std::collections::VecDeque;
+
+use automerge::{LocalChange, Path, PathElement};
+
+/// Records the inverse of local changes to a [`Document`](crate::Document) so that they can be
+/// undone and redone.
+///
+/// Undoing and redoing make new changes rather than rewriting history. Changes from patches
+/// applied to the document, such as those from other actors, aren't recorded. Recorded steps which
+/// change the same values as a patch would undo the patch too, so they are forgotten, along with
+/// the older steps which depend on them, while the others can still be undone.
+#[derive(Debug, Clone, Default)]
+pub struct UndoManager {
+    undo_stack: VecDeque<UndoStep>,
+    redo_stack: Vec<UndoStep>,
+    group: Option<UndoStep>,
+    max_depth: Option<usize>,
+}
+
+/// A single step of history.
+#[derive(Debug, Clone, Default)]
+pub struct UndoStep {
+    /// The changes which undo the step.
+    pub undo: Vec<LocalChange>,
+    /// The changes which redo the step.
+    pub redo: Vec<LocalChange>,
+}
+
+impl UndoManager {
+    /// Create a new undo manager with unlimited history.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a new undo manager which keeps at most `max_depth` steps of history.
+    pub fn with_max_depth(max_depth: usize) -> Self {
+        Self {
+            max_depth: Some(max_depth),
+            ..Self::default()
+        }
+    }
+
+    /// Start grouping changes, so that the changes until [`UndoManager::end_group`] are undone
+    /// in a single step.
+    pub fn begin_group(&mut self) {
+        if self.group.is_none() {
+            self.group = Some(UndoStep::default())
+        }
+    }
+
+    /// Stop grouping changes, recording the group as a single step.
+    pub fn end_group(&mut self) {
+        if let Some(group) = self.group.take() {
+            if !group.redo.is_empty() {
+                self.push_undo(group)
+            }
+        }
+    }
+
+    /// Whether there is a step to undo.
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty() || self.group.as_ref().map_or(false, |g| !g.redo.is_empty())
+    }
+
+    /// Whether there is a step to redo.
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    /// Forget all of the recorded history.
+    pub fn clear(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.group = None;
+    }
+
+    /// Forget the steps which change any of the paths, along with the steps done before them (or
+    /// redone after them) which change the same values, as their paths may no longer point at the
+    /// same values.
+    pub(crate) fn forget_touching(&mut self, paths: &[Path]) {
+        let mut touched = paths.iter().map(scope).collect::<Vec<_>>();
+        // the group and then the newest steps are undone first
+        if let Some(group) = &mut self.group {
+            if !group.is_independent(&mut touched) {
+                *group = UndoStep::default()
+            }
+        }
+        let keep = self
+            .undo_stack
+            .iter()
+            .rev()
+            .map(|step| step.is_independent(&mut touched))
+            .collect::<Vec<_>>();
+        let mut keep = keep.into_iter().rev();
+        self.undo_stack.retain(|_| keep.next().unwrap_or_default());
+
+        // the top of the redo stack is redone first
+        let mut touched = paths.iter().map(scope).collect::<Vec<_>>();
+        let keep = self
+            .redo_stack
+            .iter()
+            .rev()
+            .map(|step| step.is_independent(&mut touched))
+            .collect::<Vec<_>>();
+        let mut keep = keep.into_iter().rev();
+        self.redo_stack.retain(|_| keep.next().unwrap_or_default());
+    }
+
+    /// Record a local change, clearing the steps to redo.
+    pub(crate) fn record(&mut self, redo: Vec<LocalChange>, mut undo: Vec<LocalChange>) {
+        if redo.is_empty() {
+            return;
+        }
+        self.redo_stack.clear();
+        if let Some(group) = &mut self.group {
+            // the latest change is undone first
+            undo.append(&mut group.undo);
+            group.undo = undo;
+            group.redo.extend(redo);
+        } else {
+            self.push_undo(UndoStep { undo, redo })
+        }
+    }
+
+    pub(crate) fn pop_undo(&mut self) -> Option<UndoStep> {
+        self.end_group();
+        self.undo_stack.pop_back()
+    }
+
+    pub(crate) fn pop_redo(&mut self) -> Option<UndoStep> {
+        self.redo_stack.pop()
+    }
+
+    pub(crate) fn push_undo(&mut self, step: UndoStep) {
+        self.undo_stack.push_back(step);
+        if let Some(max_depth) = self.max_depth {
+            while self.undo_stack.len() > max_depth {
+                self.undo_stack.pop_front();
+            }
+        }
+    }
+
+    pub(crate) fn push_redo(&mut self, step: UndoStep) {
+        self.redo_stack.push(step)
+    }
+}
+
+impl UndoStep {
+    /// Whether the step changes none of the touched values, adding the values it changes to them
+    /// if it does.
+    fn is_independent(&self, touched: &mut Vec<Vec<PathElement>>) -> bool {
+        let scopes = self
+            .undo
+            .iter()
+            .chain(&self.redo)
+            .map(|change| scope(change.path()))
+            .collect::<Vec<_>>();
+        let independent = !scopes.iter().any(|scope| {
+            touched
+                .iter()
+                .any(|touched| scope.starts_with(touched) || touched.starts_with(scope))
+        });
+        if !independent {
+            touched.extend(scopes)
+        }
+        independent
+    }
+}
+
+/// The part of the path which a change to it can affect, up to the first list or text, as changes
+/// inside them move the elements after them.
+fn scope(path: &Path) -> Vec<PathElement> {
+    path.clone()
+        .elements()
+        .into_iter()
+        .take_while(|element| !matches!(element, PathElement::Index(_)))
+        .collect()
+}
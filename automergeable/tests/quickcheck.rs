@@ -1,7 +1,10 @@
 use std::{collections::HashMap, convert::Infallible};
 
 use automerge::{InvalidChangeRequest, Path, Primitive, Value};
-use automergeable::diff_values;
+use automerge_frontend::MutableDocument;
+use automergeable::{
+    apply_changes, diff_values, diff_values_with_moves, invert, DiffOptions, Move,
+};
 use maplit::hashmap;
 use pretty_assertions::assert_eq;
 use quickcheck::{empty_shrinker, single_shrinker, Arbitrary, Gen, QuickCheck, TestResult};
@@ -33,7 +36,7 @@ impl Arbitrary for Prim {
             4 => Primitive::Counter(i64::arbitrary(g)),
             5 => Primitive::Timestamp(i64::arbitrary(g)),
             6 => Primitive::Boolean(bool::arbitrary(g)),
-            7 => Primitive::Null, // TODO: convert this case to use an arbitrary cursor
+            7 => arbitrary_cursor(g),
             8 => Primitive::Bytes(Vec::arbitrary(g)),
             _ => Primitive::Null,
         };
@@ -109,6 +112,107 @@ impl Arbitrary for Prim {
     }
 }
 
+/// Create a cursor into some text, as cursors can only be made by a frontend holding the text.
+fn arbitrary_cursor(g: &mut Gen) -> Primitive {
+    let text = "hello world";
+    let (mut f, _) = automerge::Frontend::new_with_initial_state(Value::Map(
+        hashmap! {"text".into() => Value::Text(text.chars().map(|c| c.to_string().into()).collect())},
+    ))
+    .unwrap();
+    let index = u32::arbitrary(g) % text.len() as u32;
+    let (cursor, _) = f
+        .change::<_, _, Infallible>(None, |d| {
+            Ok(d.cursor_to_path(&Path::root().key("text").index(index)))
+        })
+        .unwrap();
+    Primitive::Cursor(cursor.unwrap())
+}
+
+/// Whether the value has a cursor, which can only be written to a frontend holding its text.
+fn has_cursor(v: &Value) -> bool {
+    match v {
+        Value::Map(m) | Value::Table(m) => m.values().any(has_cursor),
+        Value::List(v) => v.iter().any(has_cursor),
+        Value::Text(_) => false,
+        Value::Primitive(p) => matches!(p, Primitive::Cursor(_)),
+    }
+}
+
+/// The key of the text which cursors point into when they are written to a frontend.
+const CURSOR_TEXT: &str = "cursor text";
+
+/// Add the text which cursors point into to the root map.
+fn add_cursor_text(v: &mut Value) {
+    if let Value::Map(m) = v {
+        m.insert(
+            CURSOR_TEXT.into(),
+            Value::Text(
+                "hello world"
+                    .chars()
+                    .map(|c| c.to_string().into())
+                    .collect(),
+            ),
+        );
+    }
+}
+
+/// Point the cursors into the text at [`CURSOR_TEXT`] in the document, at the same index, or make
+/// them null if there is no document or text.
+///
+/// Arbitrary cursors point into text which isn't in the document so can't be written to it.
+fn point_cursors(v: &mut Value, doc: Option<&dyn MutableDocument>) {
+    match v {
+        Value::Map(m) | Value::Table(m) => m.values_mut().for_each(|v| point_cursors(v, doc)),
+        Value::List(v) => v.iter_mut().for_each(|v| point_cursors(v, doc)),
+        Value::Text(_) => {}
+        Value::Primitive(p) => {
+            if let Primitive::Cursor(cursor) = p {
+                let path = Path::root().key(CURSOR_TEXT).index(cursor.index % 11);
+                *p = doc
+                    .and_then(|doc| doc.cursor_to_path(&path))
+                    .map_or(Primitive::Null, Primitive::Cursor);
+            }
+        }
+    }
+}
+
+/// Create a frontend holding `old`, along with the backend it was applied to.
+///
+/// If either value has cursors then the text they point into is added to both, and the cursors
+/// pointed into it in the frontend, so that they can be written to it.
+fn frontend_with(old: &mut Value, new: &mut Value) -> (automerge::Frontend, automerge::Backend) {
+    let cursors = has_cursor(old) || has_cursor(new);
+    if cursors {
+        add_cursor_text(old);
+        add_cursor_text(new);
+    }
+    let mut initial = old.clone();
+    point_cursors(&mut initial, None);
+
+    let mut b = automerge::Backend::new();
+    let (mut f, c) = automerge::Frontend::new_with_initial_state(initial.clone()).unwrap();
+    let (p, _) = b.apply_local_change(c).unwrap();
+    f.apply_patch(p).unwrap();
+
+    if cursors {
+        let ((), c) = f
+            .change::<_, _, InvalidChangeRequest>(None, |d| {
+                point_cursors(old, Some(&*d));
+                point_cursors(new, Some(&*d));
+                for change in diff_values(old, &initial)? {
+                    d.add_change(change)?
+                }
+                Ok(())
+            })
+            .unwrap();
+        if let Some(c) = c {
+            let (p, _) = b.apply_local_change(c).unwrap();
+            f.apply_patch(p).unwrap();
+        }
+    }
+    (f, b)
+}
+
 #[derive(Debug, Clone, PartialEq)]
 struct Val(Value);
 
@@ -271,21 +375,18 @@ fn applying_primitive_diff_result_to_old_gives_new() {
     fn apply_diff(p1: Prim, p2: Prim) -> TestResult {
         let mut h1 = HashMap::new();
         h1.insert("k".into(), Value::Primitive(p1.0));
-        let v1 = Value::Map(h1);
+        let mut v1 = Value::Map(h1);
         let mut h2 = HashMap::new();
         h2.insert("k".into(), Value::Primitive(p2.0));
-        let v2 = Value::Map(h2);
+        let mut v2 = Value::Map(h2);
+        // new with old value
+        let (mut f, mut b) = frontend_with(&mut v2, &mut v1);
         let changes = diff_values(&v1, &v2);
         let changes = if let Ok(changes) = changes {
             changes
         } else {
             return TestResult::discard();
         };
-        let mut b = automerge::Backend::new();
-        // new with old value
-        let (mut f, c) = automerge::Frontend::new_with_initial_state(v2).unwrap();
-        let (p, _) = b.apply_local_change(c).unwrap();
-        f.apply_patch(p).unwrap();
 
         // apply changes to reach new value
         let ((), c) = f
@@ -317,7 +418,7 @@ fn applying_primitive_diff_result_to_old_gives_new() {
 
 #[test]
 fn applying_value_diff_result_to_old_gives_new() {
-    fn apply_diff(v1: Val, v2: Val) -> TestResult {
+    fn apply_diff(mut v1: Val, mut v2: Val) -> TestResult {
         if let Val(Value::Map(_)) = v1 {
         } else {
             return TestResult::discard();
@@ -326,17 +427,14 @@ fn applying_value_diff_result_to_old_gives_new() {
         } else {
             return TestResult::discard();
         }
+        // new with old value
+        let (mut f, mut b) = frontend_with(&mut v2.0, &mut v1.0);
         let changes = diff_values(&v1.0, &v2.0);
         let changes = if let Ok(changes) = changes {
             changes
         } else {
             return TestResult::discard();
         };
-        let mut b = automerge::Backend::new();
-        // new with old value
-        let (mut f, c) = automerge::Frontend::new_with_initial_state(v2.0).unwrap();
-        let (p, _) = b.apply_local_change(c).unwrap();
-        f.apply_patch(p).unwrap();
 
         // apply changes to reach new value
         let c = f.change::<_, _, InvalidChangeRequest>(None, |d| {
@@ -375,6 +473,254 @@ fn applying_value_diff_result_to_old_gives_new() {
         .quickcheck(apply_diff as fn(Val, Val) -> TestResult)
 }
 
+#[test]
+fn applying_list_diff_result_to_old_gives_new() {
+    fn apply_diff(l1: Vec<Val>, l2: Vec<Val>) -> TestResult {
+        let mut v1 =
+            Value::Map(hashmap! {"l".into() => Value::List(l1.into_iter().map(|v| v.0).collect())});
+        let mut v2 =
+            Value::Map(hashmap! {"l".into() => Value::List(l2.into_iter().map(|v| v.0).collect())});
+        // new with old value
+        let (mut f, mut b) = frontend_with(&mut v2, &mut v1);
+        let changes = diff_values(&v1, &v2);
+        let changes = if let Ok(changes) = changes {
+            changes
+        } else {
+            return TestResult::discard();
+        };
+
+        // apply changes to reach new value
+        let c = f.change::<_, _, InvalidChangeRequest>(None, |d| {
+            for change in &changes {
+                d.add_change(change.clone())?
+            }
+            Ok(())
+        });
+        if let Ok(((), c)) = c {
+            if let Some(c) = c {
+                let (p, _) = b.apply_local_change(c).unwrap();
+                if let Err(e) = f.apply_patch(p) {
+                    println!("{:?} {:?}", changes, e);
+                    return TestResult::failed();
+                }
+            }
+        } else {
+            println!("changes {:?} {:?}", changes, c);
+            return TestResult::failed();
+        }
+
+        let val = f.get_value(&Path::root()).unwrap();
+        if val == v1 {
+            TestResult::passed()
+        } else {
+            println!("changes {:?}", changes);
+            println!("expected: {:?}", v1);
+            println!("found   : {:?}", val);
+            TestResult::failed()
+        }
+    }
+
+    QuickCheck::new()
+        .tests(100_000)
+        .gen(Gen::new(30))
+        .quickcheck(apply_diff as fn(Vec<Val>, Vec<Val>) -> TestResult)
+}
+
+#[test]
+fn inserting_into_list_gives_single_insert() {
+    fn single_insert(list: Vec<Val>, index: usize, val: Val) -> TestResult {
+        let old = list.into_iter().map(|v| v.0).collect::<Vec<_>>();
+        let mut new = old.clone();
+        new.insert(index % (old.len() + 1), val.0);
+        let changes = diff_values(&Value::List(new), &Value::List(old));
+        match changes {
+            Ok(changes) if changes.len() == 1 => TestResult::passed(),
+            Ok(changes) => {
+                println!("{:?}", changes);
+                TestResult::failed()
+            }
+            Err(_) => TestResult::discard(),
+        }
+    }
+
+    QuickCheck::new()
+        .tests(100_000)
+        .gen(Gen::new(30))
+        .quickcheck(single_insert as fn(Vec<Val>, usize, Val) -> TestResult)
+}
+
+#[test]
+fn moving_list_element_is_detected() {
+    fn single_move(list: Vec<Val>, from: usize, to: usize) -> TestResult {
+        let old = list.into_iter().map(|v| v.0).collect::<Vec<_>>();
+        // the moved element is only known if every element is distinct (and equal to itself)
+        if old.is_empty()
+            || !old
+                .iter()
+                .all(|a| old.iter().filter(|b| a == *b).count() == 1)
+        {
+            return TestResult::discard();
+        }
+        let (from, to) = (from % old.len(), to % old.len());
+        let mut new = old.clone();
+        let moved = new.remove(from);
+        new.insert(to, moved);
+        match diff_values_with_moves(
+            &Value::List(new.clone()),
+            &Value::List(old.clone()),
+            &DiffOptions::default(),
+        ) {
+            Ok((changes, moves)) => {
+                let expected = |old_index, new_index| Move {
+                    old_index,
+                    new_index,
+                    path: Path::root(),
+                };
+                let moves_match = match (from as isize - to as isize).abs() {
+                    0 => moves.is_empty() && changes.is_empty(),
+                    // swapping neighbours can be seen as moving either of them
+                    1 => {
+                        changes.len() == 2
+                            && (moves == vec![expected(from, to)]
+                                || moves == vec![expected(to, from)])
+                    }
+                    _ => changes.len() == 2 && moves == vec![expected(from, to)],
+                };
+                if moves_match {
+                    TestResult::passed()
+                } else {
+                    println!("{:?} {:?}", changes, moves);
+                    TestResult::failed()
+                }
+            }
+            Err(_) => TestResult::discard(),
+        }
+    }
+
+    QuickCheck::new()
+        .tests(100_000)
+        .gen(Gen::new(30))
+        .quickcheck(single_move as fn(Vec<Val>, usize, usize) -> TestResult)
+}
+
+#[test]
+fn applying_inverse_after_diff_gives_old() {
+    fn apply(
+        f: &mut automerge::Frontend,
+        b: &mut automerge::Backend,
+        changes: &[automerge::LocalChange],
+    ) -> bool {
+        let c = f.change::<_, _, InvalidChangeRequest>(None, |d| {
+            for change in changes {
+                d.add_change(change.clone())?
+            }
+            Ok(())
+        });
+        match c {
+            Ok(((), Some(c))) => {
+                let (p, _) = b.apply_local_change(c).unwrap();
+                f.apply_patch(p).is_ok()
+            }
+            Ok(((), None)) => true,
+            Err(_) => false,
+        }
+    }
+
+    fn round_trip(mut v1: Val, mut v2: Val) -> TestResult {
+        if let (Val(Value::Map(_)), Val(Value::Map(_))) = (&v1, &v2) {
+        } else {
+            return TestResult::discard();
+        }
+        // new with old value
+        let (mut f, mut b) = frontend_with(&mut v2.0, &mut v1.0);
+        let (forward, inverse) = if let Ok(changes) = invert(&v1.0, &v2.0) {
+            changes
+        } else {
+            return TestResult::discard();
+        };
+
+        if !apply(&mut f, &mut b, &forward) || f.get_value(&Path::root()).unwrap() != v1.0 {
+            println!("forward {:?}", forward);
+            return TestResult::failed();
+        }
+        if !apply(&mut f, &mut b, &inverse) {
+            println!("inverse {:?}", inverse);
+            return TestResult::failed();
+        }
+
+        let val = f.get_value(&Path::root()).unwrap();
+        if val == v2.0 {
+            TestResult::passed()
+        } else {
+            println!("inverse {:?}", inverse);
+            println!("expected: {:?}", v2);
+            println!("found   : {:?}", val);
+            TestResult::failed()
+        }
+    }
+
+    QuickCheck::new()
+        .tests(100_000)
+        .gen(Gen::new(30))
+        .quickcheck(round_trip as fn(Val, Val) -> TestResult)
+}
+
+#[test]
+fn applying_diff_in_memory_gives_new() {
+    fn apply_diff(v1: Val, v2: Val) -> TestResult {
+        if let (Val(Value::Map(_)), Val(Value::Map(_))) = (&v1, &v2) {
+        } else {
+            return TestResult::discard();
+        }
+        let changes = if let Ok(changes) = diff_values(&v1.0, &v2.0) {
+            changes
+        } else {
+            return TestResult::discard();
+        };
+        let mut val = v2.0;
+        if let Err(e) = apply_changes(&mut val, &changes) {
+            println!("changes {:?} {:?}", changes, e);
+            return TestResult::failed();
+        }
+        if val == v1.0 {
+            TestResult::passed()
+        } else {
+            println!("changes {:?}", changes);
+            println!("expected: {:?}", v1);
+            println!("found   : {:?}", val);
+            TestResult::failed()
+        }
+    }
+
+    QuickCheck::new()
+        .tests(1_000_000)
+        .gen(Gen::new(30))
+        .quickcheck(apply_diff as fn(Val, Val) -> TestResult)
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn parallel_diff_matches_serial() {
+    fn same_diff(v1: Val, v2: Val) -> bool {
+        let serial = DiffOptions {
+            parallel_threshold: usize::MAX,
+            ..DiffOptions::default()
+        };
+        let parallel = DiffOptions {
+            parallel_threshold: 0,
+            ..DiffOptions::default()
+        };
+        // errors aren't comparable so compare the debug output
+        format!("{:?}", diff_values_with_moves(&v1.0, &v2.0, &serial))
+            == format!("{:?}", diff_values_with_moves(&v1.0, &v2.0, &parallel))
+    }
+
+    QuickCheck::new()
+        .tests(100_000)
+        .gen(Gen::new(30))
+        .quickcheck(same_diff as fn(Val, Val) -> bool)
+}
+
 #[test]
 fn broken_reordering_of_values_2() {
     let v1 = Val(Value::Map(
@@ -418,18 +764,47 @@ fn broken_reordering_of_values_2() {
 
 #[test]
 fn save_then_load() {
-    fn apply_diff(vals: Vec<Val>) -> TestResult {
+    fn apply_diff(mut vals: Vec<Val>) -> TestResult {
         for val in &vals {
             if let Val(Value::Map(_)) = val {
             } else {
                 return TestResult::discard();
             }
         }
+        if vals.iter().any(|val| has_cursor(&val.0)) {
+            for val in &mut vals {
+                add_cursor_text(&mut val.0)
+            }
+        }
 
         let mut backend_bytes = Vec::new();
         let mut old: Option<Val> = None;
         let mut change_history = Vec::new();
-        for val in vals {
+        for mut val in vals {
+            let mut backend = if backend_bytes.is_empty() {
+                automerge::Backend::new()
+            } else {
+                let b = automerge::Backend::load(backend_bytes);
+                if let Ok(b) = b {
+                    b
+                } else {
+                    println!("changes: {:?}", change_history);
+                    println!("error loading: {:?}", b);
+                    return TestResult::failed();
+                }
+            };
+
+            let mut frontend = automerge::Frontend::new();
+            let patch = backend.get_patch().unwrap();
+            frontend.apply_patch(patch).unwrap();
+            // cursors can only point into the text once it is in the document
+            frontend
+                .change::<_, _, Infallible>(None, |d| {
+                    point_cursors(&mut val.0, Some(&*d));
+                    Ok(())
+                })
+                .unwrap();
+
             let changes = diff_values(&val.0, &old.unwrap_or_default().0);
             match changes {
                 Err(InvalidChangeRequest::CannotOverwriteCounter { .. }) => {
@@ -442,22 +817,6 @@ fn save_then_load() {
                 Ok(changes) => {
                     change_history.push(changes.clone());
                     old = Some(val);
-                    let mut backend = if backend_bytes.is_empty() {
-                        automerge::Backend::new()
-                    } else {
-                        let b = automerge::Backend::load(backend_bytes);
-                        if let Ok(b) = b {
-                            b
-                        } else {
-                            println!("changes: {:?}", change_history);
-                            println!("error loading: {:?}", b);
-                            return TestResult::failed();
-                        }
-                    };
-
-                    let mut frontend = automerge::Frontend::new();
-                    let patch = backend.get_patch().unwrap();
-                    frontend.apply_patch(patch).unwrap();
 
                     let ((), c) = frontend
                         .change::<_, _, InvalidChangeRequest>(None, |d| {
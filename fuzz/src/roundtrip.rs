@@ -1,11 +1,21 @@
 #![no_main]
 
-use automerge::{Backend, InvalidChangeRequest, Primitive, Value};
-use automergeable::{unicode_segmentation::UnicodeSegmentation, DocumentChangeError};
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use automerge::{Backend, InvalidChangeRequest, Path, Primitive, Value};
+use automergeable::{
+    unicode_segmentation::UnicodeSegmentation, Cursors, DocumentChangeError, FromAutomerge, Table,
+    ToAutomerge,
+};
 use libfuzzer_sys::fuzz_target;
 use pretty_assertions::assert_eq;
 
 fuzz_target!(|values: Vec<automerge::Value>| {
+    let values = values.into_iter().map(uuid_row_ids).collect::<Vec<_>>();
+
     for val in &values {
         // ensure the root is always a map
         if let Value::Map(_) = val {
@@ -13,16 +23,6 @@ fuzz_target!(|values: Vec<automerge::Value>| {
             return;
         }
 
-        // don't work with cursors for now
-        if has_cursor(val) {
-            return;
-        }
-
-        // don't work with cursors for now
-        if has_table(val) {
-            return;
-        }
-
         // don't allow empty text
         if has_empty_text(val) {
             return;
@@ -38,8 +38,12 @@ fuzz_target!(|values: Vec<automerge::Value>| {
 
     let mut backend_bytes = Vec::new();
 
-    for val in values {
-        let change = doc.change::<_, _, InvalidChangeRequest>(|old| {
+    for mut val in values {
+        let change = doc.change_with_cursors::<_, _, InvalidChangeRequest>(|old, cursors| {
+            // arbitrary cursors point into text which isn't in the document, so point them into
+            // text which is and which this change keeps
+            let texts = kept_texts(old, &val, Path::root());
+            point_cursors(&mut val, &texts, cursors);
             *old = val.clone();
             Ok(())
         });
@@ -54,6 +58,7 @@ fuzz_target!(|values: Vec<automerge::Value>| {
                     };
                     let (patch, _) = backend.apply_local_change(c).unwrap();
                     assert_eq!(doc.get(), &val);
+                    assert_tables_roundtrip(&val);
                     doc.apply_patch(patch).unwrap();
 
                     let doc_val = doc.get();
@@ -69,6 +74,10 @@ fuzz_target!(|values: Vec<automerge::Value>| {
                         assert_eq!(doc_val, &val);
                     }
                     backend_bytes = backend.save().unwrap();
+
+                    let (loaded, _) =
+                        automergeable::Document::<Value, _>::load(backend_bytes.clone()).unwrap();
+                    assert_eq!(loaded.get(), doc.get());
                 }
             }
             Err(DocumentChangeError::InvalidChangeRequest(
@@ -82,23 +91,70 @@ fuzz_target!(|values: Vec<automerge::Value>| {
     }
 });
 
-fn has_cursor(v: &Value) -> bool {
+/// Find the paths and lengths of the non empty texts in `old` which are also in `new`, only
+/// through maps so that their paths don't change.
+fn kept_texts(old: &Value, new: &Value, path: Path) -> Vec<(Path, u32)> {
+    match (old, new) {
+        (Value::Map(old), Value::Map(new)) | (Value::Table(old), Value::Table(new)) => old
+            .iter()
+            .filter_map(|(k, old)| new.get(k).map(|new| (k, old, new)))
+            .flat_map(|(k, old, new)| kept_texts(old, new, path.clone().key(k.as_str())))
+            .collect(),
+        (Value::Text(old), Value::Text(new)) if old == new && !old.is_empty() => {
+            vec![(path, old.len() as u32)]
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Replace cursors with ones into the texts, chosen by their index, or null if there are none.
+fn point_cursors(v: &mut Value, texts: &[(Path, u32)], cursors: &Cursors) {
     match v {
-        Value::Map(m) | Value::Table(m) => m.values().any(|v| has_cursor(v)),
-        Value::List(v) => v.iter().any(|i| has_cursor(i)),
-        Value::Text(_) => false,
+        Value::Map(m) | Value::Table(m) => m
+            .values_mut()
+            .for_each(|v| point_cursors(v, texts, cursors)),
+        Value::List(v) => v.iter_mut().for_each(|v| point_cursors(v, texts, cursors)),
+        Value::Text(_) => {}
         Value::Primitive(p) => {
-            matches!(p, Primitive::Cursor(_))
+            if let Primitive::Cursor(cursor) = p {
+                let cursor = texts
+                    .get(cursor.index as usize % texts.len().max(1))
+                    .and_then(|(path, len)| cursors.cursor(&path.clone().index(cursor.index % len)));
+                *p = cursor.map_or(Primitive::Null, |c| Primitive::Cursor(c.into_inner()));
+            }
         }
     }
 }
 
-fn has_table(v: &Value) -> bool {
+/// Replace the keys of tables with UUIDs derived from them, so that they are valid row ids.
+fn uuid_row_ids(v: Value) -> Value {
     match v {
-        Value::Map(m) => m.values().any(|v| has_table(v)),
-        Value::Table(_) => true,
-        Value::List(v) => v.iter().any(|i| has_table(i)),
-        Value::Text(_) | Value::Primitive(_) => false,
+        Value::Map(m) => Value::Map(m.into_iter().map(|(k, v)| (k, uuid_row_ids(v))).collect()),
+        Value::Table(m) => Value::Table(
+            m.into_iter()
+                .map(|(k, v)| {
+                    let mut hasher = DefaultHasher::new();
+                    k.hash(&mut hasher);
+                    let id = uuid::Uuid::from_u128(u128::from(hasher.finish()));
+                    (id.to_string().into(), uuid_row_ids(v))
+                })
+                .collect(),
+        ),
+        Value::List(v) => Value::List(v.into_iter().map(uuid_row_ids).collect()),
+        v @ Value::Text(_) | v @ Value::Primitive(_) => v,
+    }
+}
+
+fn assert_tables_roundtrip(v: &Value) {
+    match v {
+        Value::Map(m) => m.values().for_each(assert_tables_roundtrip),
+        Value::Table(m) => {
+            let table = Table::<Value>::from_automerge(v).unwrap();
+            assert_eq!(&table.to_automerge(), v);
+            m.values().for_each(assert_tables_roundtrip)
+        }
+        Value::List(v) => v.iter().for_each(assert_tables_roundtrip),
+        Value::Text(_) | Value::Primitive(_) => {}
     }
 }
 